@@ -0,0 +1,180 @@
+use crate::hasher::KeyHasher;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hash};
+
+/// The storage backing the entries of a subscription map
+pub(crate) trait MapBackend<K, E> {
+    fn get<Q>(&self, key: &Q) -> Option<&E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord;
+    fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord;
+    fn insert(&mut self, key: K, entry: E) -> Option<E>;
+    fn remove(&mut self, key: &K) -> Option<E>;
+    fn len(&self) -> usize;
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, &'a E)> + 'a>;
+    /// Remove and return all entries at once
+    fn take_all(&mut self) -> Vec<(K, E)>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        self.get(key).is_some()
+    }
+}
+
+impl<K, E> MapBackend<K, E> for BTreeMap<K, E>
+where
+    K: Ord,
+{
+    fn get<Q>(&self, key: &Q) -> Option<&E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        BTreeMap::get(self, key)
+    }
+
+    fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        BTreeMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: K, entry: E) -> Option<E> {
+        BTreeMap::insert(self, key, entry)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<E> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, &'a E)> + 'a> {
+        Box::new(BTreeMap::iter(self))
+    }
+
+    fn take_all(&mut self) -> Vec<(K, E)> {
+        std::mem::take(self).into_iter().collect()
+    }
+}
+
+impl<K, E, S> MapBackend<K, E> for HashMap<K, E, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn get<Q>(&self, key: &Q) -> Option<&E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        HashMap::get(self, key)
+    }
+
+    fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        HashMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: K, entry: E) -> Option<E> {
+        HashMap::insert(self, key, entry)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<E> {
+        HashMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, &'a E)> + 'a> {
+        Box::new(HashMap::iter(self))
+    }
+
+    fn take_all(&mut self) -> Vec<(K, E)> {
+        self.drain().collect()
+    }
+}
+
+/// The backend selected at construction of a subscription map
+#[derive(Debug)]
+pub(crate) enum Storage<K, E>
+where
+    K: Debug + Eq + Hash + Ord,
+{
+    /// Keeps the keys ordered, which allows to iterate them in ascending order
+    Ordered(BTreeMap<K, E>),
+    /// Faster point lookups for large maps, but keys are unordered
+    Hashed(HashMap<K, E, KeyHasher>),
+}
+
+macro_rules! dispatch {
+    ($storage:expr, $map:ident => $body:expr) => {
+        match $storage {
+            Storage::Ordered($map) => $body,
+            Storage::Hashed($map) => $body,
+        }
+    };
+}
+
+impl<K, E> MapBackend<K, E> for Storage<K, E>
+where
+    K: Debug + Eq + Hash + Ord,
+{
+    fn get<Q>(&self, key: &Q) -> Option<&E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        dispatch!(self, map => MapBackend::get(map, key))
+    }
+
+    fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut E>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        dispatch!(self, map => MapBackend::get_mut(map, key))
+    }
+
+    fn insert(&mut self, key: K, entry: E) -> Option<E> {
+        dispatch!(self, map => MapBackend::insert(map, key, entry))
+    }
+
+    fn remove(&mut self, key: &K) -> Option<E> {
+        dispatch!(self, map => MapBackend::remove(map, key))
+    }
+
+    fn len(&self) -> usize {
+        dispatch!(self, map => MapBackend::len(map))
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, &'a E)> + 'a> {
+        dispatch!(self, map => MapBackend::iter(map))
+    }
+
+    fn take_all(&mut self) -> Vec<(K, E)> {
+        dispatch!(self, map => MapBackend::take_all(map))
+    }
+}
@@ -0,0 +1,304 @@
+use crate::backend::MapBackend;
+use crate::error::BufferFullError;
+use crate::{SubscriptionMap, SubscriptionRef};
+use async_observable::Observable;
+use futures::task::AtomicWaker;
+use futures::Stream;
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+/// What publishing does while the buffer of a bounded subscription is full, see
+/// [`SubscriptionMap::subscribe_bounded`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until the subscriber made room. The entry isn't locked meanwhile, so only publishers
+    /// are held up, while readers of the entry aren't.
+    #[default]
+    Wait,
+    /// Fail with a [`BufferFullError`](crate::BufferFullError) and drop the value
+    Reject,
+}
+
+/// The buffer of a bounded subscription, shared between the entry state and the subscriber
+struct Queue<V> {
+    values: Mutex<VecDeque<V>>,
+    capacity: usize,
+    backpressure: Backpressure,
+    /// Woken when a value is pushed or the entry is gone
+    consumer: AtomicWaker,
+    /// Modified when a value is popped or the subscriber is gone, forked by every publisher
+    /// waiting for room
+    room: Observable<()>,
+    closed: AtomicBool,
+}
+
+impl<V> Queue<V> {
+    fn values(&self) -> MutexGuard<'_, VecDeque<V>> {
+        self.values.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_full(&self) -> bool {
+        self.values().len() >= self.capacity
+    }
+}
+
+/// The bounded subscribers of an entry, every emitted value is queued for all of them
+///
+/// Publishers check for room before emitting, see [`BoundedSinks::reserve`]. Those which can't
+/// unlock the entry to wait, e.g. batches holding several entries, treat every full buffer like
+/// a rejecting one.
+pub(crate) struct BoundedSinks<V>(Vec<Arc<Queue<V>>>);
+
+impl<V> BoundedSinks<V>
+where
+    V: Clone,
+{
+    fn add(&mut self, queue: Arc<Queue<V>>) {
+        self.0.push(queue);
+    }
+
+    /// Check whether every buffer has room for another value, failing right away if a rejecting
+    /// one is full. Otherwise the full buffers of waiting subscribers are returned, which the
+    /// publisher waits for without holding the entry lock before checking again. Buffers of
+    /// dropped subscribers are discarded.
+    pub fn reserve(&mut self) -> Result<Option<FullBuffers<V>>, BufferFullError> {
+        self.0.retain(|queue| Arc::strong_count(queue) > 1);
+
+        let mut full = Vec::new();
+
+        for queue in self.0.iter().filter(|queue| queue.is_full()) {
+            if queue.backpressure == Backpressure::Reject {
+                return Err(BufferFullError::new(queue.capacity));
+            }
+
+            // forked while the entry is locked, so no value popped afterwards gets lost
+            let mut room = queue.room.fork();
+            room.synchronize();
+            full.push((queue.clone(), room));
+        }
+
+        Ok((!full.is_empty()).then_some(FullBuffers(full)))
+    }
+
+    /// Queue the emitted value for all subscribers
+    pub fn send(&mut self, value: &V) {
+        self.0.retain(|queue| Arc::strong_count(queue) > 1);
+
+        for queue in &self.0 {
+            queue.values().push_back(value.clone());
+            queue.consumer.wake();
+        }
+    }
+}
+
+/// The full buffers a publisher has to wait for, see [`BoundedSinks::reserve`]
+pub(crate) struct FullBuffers<V>(Vec<(Arc<Queue<V>>, Observable<()>)>);
+
+impl<V> FullBuffers<V> {
+    /// The error of publishers which can't wait for the buffers
+    pub fn error(&self) -> BufferFullError {
+        BufferFullError::new(self.0[0].0.capacity)
+    }
+
+    /// Wait until every buffer has room for another value, or its subscriber is gone
+    pub async fn wait(self) {
+        for (queue, mut room) in self.0 {
+            while queue.is_full() {
+                room.next().await;
+            }
+        }
+    }
+}
+
+impl<V> Default for BoundedSinks<V> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<V> Drop for BoundedSinks<V> {
+    fn drop(&mut self) {
+        for queue in &self.0 {
+            queue.closed.store(true, Ordering::SeqCst);
+            queue.consumer.wake();
+        }
+    }
+}
+
+impl<V> Debug for BoundedSinks<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BoundedSinks").field(&self.0.len()).finish()
+    }
+}
+
+/// A subscription receiving every value in order through a bounded buffer, see
+/// [`SubscriptionMap::subscribe_bounded`].
+///
+/// Unlike a [`SubscriptionRef`], which only sees the latest value, no intermediate value is
+/// skipped. Instead publishers are held up, or fail, while the buffer is full. The stream ends
+/// once the entry is removed from the map and the buffer is drained.
+pub struct BoundedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    subscription: SubscriptionRef<K, V>,
+    queue: Arc<Queue<V>>,
+    /// Announces room to waiting publishers
+    room: Observable<()>,
+}
+
+impl<K, V> BoundedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// The key of the subscribed entry
+    pub fn key(&self) -> K {
+        self.subscription.key()
+    }
+
+    /// The number of values waiting to be received
+    pub fn len(&self) -> usize {
+        self.queue.values().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of values the buffer holds before publishers are held up
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity
+    }
+}
+
+impl<K, V> Unpin for BoundedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+}
+
+impl<K, V> Stream for BoundedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    type Item = V;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let queue = &this.queue;
+
+        for registered in [false, true] {
+            if let Some(value) = queue.values().pop_front() {
+                this.room.modify(|_| {});
+                return Poll::Ready(Some(value));
+            }
+
+            if queue.closed.load(Ordering::SeqCst) {
+                return Poll::Ready(None);
+            }
+
+            // check once more after registering, a value might have been pushed in between
+            if !registered {
+                queue.consumer.register(cx.waker());
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<K, V> Drop for BoundedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn drop(&mut self) {
+        // a publisher waiting for room doesn't have to wait for this subscriber anymore
+        self.queue.values().clear();
+        self.room.modify(|_| {});
+    }
+}
+
+impl<K, V> Debug for BoundedSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoundedSubscription")
+            .field("key", &self.subscription.key())
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// Subscribe to every value of the entry, starting with the current one, through a buffer
+    /// holding up to `capacity` values. While the buffer is full, publishing to the entry waits
+    /// or fails depending on the backpressure, for consumers which must process every update.
+    ///
+    /// Every way of publishing to the entry applies backpressure, publishing a value which turns
+    /// out unchanged, e.g. via [`SubscriptionMap::publish_if_changed`], never fails for a full
+    /// buffer though, as nothing is queued. Batches, transactions and transfers wait for room
+    /// while locking their entries, but can't wait again for a second value to the same entry,
+    /// just like an [`OccupiedEntry`](crate::OccupiedEntry) holding the map locked can't wait at
+    /// all, so they fail for any full buffer. Debounced values are kept pending until there is
+    /// room for them. Returns `None` if the key isn't present.
+    pub async fn subscribe_bounded<Q>(
+        &self,
+        key: &Q,
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> Option<BoundedSubscription<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        assert!(
+            capacity > 0,
+            "bounded subscription capacity must be at least one"
+        );
+
+        // subscribed and queued to the same entry, even if the key is replaced in between
+        let (subscription, state) = {
+            let state = self.0.for_key(key).read().await;
+            let entry = state.entries.get(key)?;
+            let subscription = SubscriptionRef::new(self.clone(), entry).unwrap();
+
+            (subscription, entry.state.clone())
+        };
+        let mut state = state.lock().await;
+
+        let queue = Arc::new(Queue {
+            values: Mutex::new(VecDeque::from([state.observable.latest()])),
+            capacity,
+            backpressure,
+            consumer: AtomicWaker::new(),
+            room: Observable::new(()),
+            closed: AtomicBool::new(false),
+        });
+        // queued under the entry lock, so no value can slip in between
+        state.bounded.add(queue.clone());
+
+        Some(BoundedSubscription {
+            subscription,
+            room: queue.room.fork(),
+            queue,
+        })
+    }
+}
@@ -0,0 +1,235 @@
+use crate::backend::Storage;
+use crate::debounce::Debounce;
+use crate::detect::{ChangeDetect, Detector};
+use crate::hasher::{KeyHasher, KeyHashing};
+use crate::ratelimit::RateLimiter;
+use crate::shards::Shards;
+use crate::{spawn_debounce_flush_all, spawn_housekeeping, MapState, SubscriptionMap};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// What to do when inserting into a full map, see [`SubscriptionMapBuilder::capacity`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Refuse to insert new entries
+    Reject,
+    /// Evict the entry which was published to least recently, see [`SubscriptionMap::evict`].
+    /// Pinned and persistent entries are never evicted, if only those are left inserting fails.
+    EvictLeastRecentlyPublished,
+}
+
+type SpawnFlush<K, V> = fn(Weak<Shards<K, V>>, Duration);
+
+/// Configures a subscription map, see [`SubscriptionMap::builder`].
+///
+/// By default the built map is equivalent to [`SubscriptionMap::new`]: a single shard with
+/// ordered keys, whose entries are removed as soon as they are unused and never expire.
+#[derive(Debug)]
+pub struct SubscriptionMapBuilder<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    hashed: bool,
+    hasher: KeyHasher,
+    shards: usize,
+    linger: Option<Duration>,
+    ttl: Option<Duration>,
+    capacity: Option<(usize, EvictionPolicy)>,
+    detect: Detector<V>,
+    history: Option<usize>,
+    /// The debouncing of new entries and the task flushing their pending values, which needs
+    /// `V: PartialEq`
+    debounce: Option<(Debounce<V>, SpawnFlush<K, V>)>,
+    rate_limit: Option<RateLimiter>,
+    _types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> SubscriptionMapBuilder<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            hashed: false,
+            hasher: KeyHasher::default(),
+            shards: 1,
+            linger: None,
+            ttl: None,
+            capacity: None,
+            detect: Detector::default(),
+            history: None,
+            debounce: None,
+            rate_limit: None,
+            _types: PhantomData,
+        }
+    }
+
+    /// Store entries in a `HashMap` instead of a `BTreeMap`, see [`SubscriptionMap::hashed`]
+    pub fn hashed(mut self) -> Self {
+        self.hashed = true;
+        self
+    }
+
+    /// Store entries in a `HashMap` hashing keys with the given hash function, e.g. a faster one
+    /// than the std default for trusted keys. Implies [`SubscriptionMapBuilder::hashed`]. Shards
+    /// are selected with the same hash function, see [`SubscriptionMapBuilder::shards`].
+    pub fn hasher(mut self, hashing: KeyHashing) -> Self {
+        self.hashed = true;
+        self.hasher = KeyHasher::new(hashing);
+        self
+    }
+
+    /// Split the map into the given number of shards, see [`SubscriptionMap::sharded`]. A map
+    /// has at least one shard, so zero is treated like one.
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = shards.max(1);
+        self
+    }
+
+    /// Keep unused entries for the given duration, see [`SubscriptionMap::with_linger`]
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// Evict entries which weren't published to within the given duration, regardless of their
+    /// subscriptions. Subscribers are notified like for [`SubscriptionMap::evict`].
+    ///
+    /// Stale entries are evicted by a background task after at least the given duration. Only
+    /// values published through the map count, publishing directly through the observable of a
+    /// subscription ref bypasses the map.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Limit the number of entries of the map, the policy decides what happens once it is full.
+    ///
+    /// With multiple shards the capacity is split between them, so the map never holds more than
+    /// `capacity` entries in total. Every shard enforces its own share though, so a shard might
+    /// be full before the whole map is, and shards get no room at all if there are more shards
+    /// than entries. The `try_` methods, like [`SubscriptionMap::try_get_or_insert`],
+    /// report a full map as [`InsertError::Full`](crate::InsertError::Full), the other inserting
+    /// methods panic.
+    pub fn capacity(mut self, capacity: usize, policy: EvictionPolicy) -> Self {
+        self.capacity = Some((capacity, policy));
+        self
+    }
+
+    /// Decide whether a value is a change worth publishing with the given detection instead of
+    /// `PartialEq`, e.g. to ignore timestamps or compare floats within a tolerance. Applies to
+    /// [`SubscriptionMap::publish_if_changed`] and every method built on top of it, as well as to
+    /// debounced values.
+    pub fn change_detect<D>(mut self, detect: D) -> Self
+    where
+        D: ChangeDetect<V> + 'static,
+    {
+        self.detect = Detector::new(detect);
+        self
+    }
+
+    /// Retain the last `capacity` published values of every entry, starting with its initial
+    /// value, see [`SubscriptionMap::history`]. Subscribers can replay them like for
+    /// [`SubscriptionMap::get_or_insert_buffered`].
+    pub fn history(mut self, capacity: usize) -> Self {
+        self.history = Some(capacity);
+        self
+    }
+
+    /// Coalesce rapid publishes of every entry: at most one value per interval is emitted to
+    /// subscribers and the most recent one is always delivered once the interval elapsed, like
+    /// [`SubscriptionMap::get_or_insert_debounced`] for all keys. Pending values are emitted by a
+    /// background task, which stops as soon as the map is dropped.
+    ///
+    /// Replaces [`SubscriptionMapBuilder::debounce`] if configured before.
+    pub fn coalesce(mut self, interval: Duration) -> Self
+    where
+        K: Send + Sync + 'static,
+        V: PartialEq + Send + Sync + 'static,
+    {
+        self.debounce = Some((Debounce::new(interval), spawn_debounce_flush_all::<K, V>));
+        self
+    }
+
+    /// Debounce publishes to every entry: a value is only emitted to subscribers once no other
+    /// value was published to the entry for the given duration, so bursts deliver just their
+    /// last value. Pending values are emitted by a background task like for
+    /// [`SubscriptionMapBuilder::coalesce`], which this replaces if configured before.
+    pub fn debounce(mut self, quiet: Duration) -> Self
+    where
+        K: Send + Sync + 'static,
+        V: PartialEq + Send + Sync + 'static,
+    {
+        self.debounce = Some((Debounce::quiet(quiet), spawn_debounce_flush_all::<K, V>));
+        self
+    }
+
+    /// Limit the publishes to every entry with a token bucket: an entry accepts a burst of
+    /// `burst` publishes, afterwards one more per `refill`. Publishing to an entry exceeding its
+    /// limit fails with a [`RateLimitedError`](crate::RateLimitedError) and the value is dropped.
+    ///
+    /// Every attempt takes a token, including values which turn out unchanged. Methods which
+    /// don't return an `anyhow::Result` report the refusal as a
+    /// [`PublishError`](crate::PublishError) instead, transactions and transfers only publish if
+    /// every written entry has a token left.
+    pub fn rate_limit(mut self, burst: u32, refill: Duration) -> Self {
+        assert!(burst > 0, "rate limit burst must be at least one");
+        self.rate_limit = Some(RateLimiter::new(burst, refill));
+        self
+    }
+
+    /// Create the map and spawn its background task, if lingering or expiry are configured
+    pub fn build(self) -> SubscriptionMap<K, V>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let shards = (0..self.shards)
+            .map(|index| {
+                let storage = if self.hashed {
+                    Storage::Hashed(HashMap::with_hasher(self.hasher.clone()))
+                } else {
+                    Storage::Ordered(BTreeMap::new())
+                };
+
+                let mut state = MapState::new(storage);
+                state.linger = self.linger;
+                state.ttl = self.ttl;
+                state.detect = self.detect.clone();
+                state.history = self.history;
+                state.debounce = self.debounce.as_ref().map(|(debounce, _)| debounce.clone());
+                state.rate_limit = self.rate_limit.clone();
+                // the remainder goes to the first shards, so the shares add up to the capacity
+                state.capacity = self.capacity.map(|(capacity, policy)| {
+                    let share =
+                        capacity / self.shards + usize::from(index < capacity % self.shards);
+                    (share, policy)
+                });
+                state
+            })
+            .collect();
+
+        let map = SubscriptionMap(Arc::new(Shards::new(shards, self.hasher)));
+
+        let interval = match (self.linger, self.ttl) {
+            (Some(linger), Some(ttl)) => Some(linger.min(ttl)),
+            (linger, ttl) => linger.or(ttl),
+        };
+
+        if let Some(interval) = interval {
+            spawn_housekeeping(Arc::downgrade(&map.0), interval);
+        }
+
+        if let Some((debounce, spawn_flush)) = self.debounce {
+            spawn_flush(Arc::downgrade(&map.0), debounce.interval());
+        }
+
+        map
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{runtime, Replay, SubscriptionMap, SubscriptionRef};
+use futures::future::{self, AbortHandle};
+use std::borrow::Borrow;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::Hash;
+
+/// Keeps a callback registered via [`SubscriptionMap::on_change`] running, the callback is
+/// stopped once the handle is dropped or cancelled.
+#[derive(Debug)]
+#[must_use = "the callback is stopped as soon as the handle is dropped"]
+pub struct CallbackHandle {
+    handle: AbortHandle,
+}
+
+impl CallbackHandle {
+    /// Stop the callback, same as dropping the handle
+    pub fn cancel(self) {}
+
+    /// Whether the callback stopped, because the handle was cancelled or the entry is gone for
+    /// good, see [`SubscriptionRef::next_or_closed`]
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_aborted()
+    }
+}
+
+impl Drop for CallbackHandle {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Call the callback with every new value of the entry until the returned handle is dropped,
+    /// for consumers without a task loop to poll a subscription, like GUI or FFI layers. Returns
+    /// `None` if the key isn't present.
+    ///
+    /// The callback runs on a background task, which holds a subscription to the entry and stops
+    /// once the entry is evicted or the map closed. Like every subscriber, the callback only sees
+    /// the latest value and might skip intermediate ones.
+    pub async fn on_change<Q, F>(&self, key: &Q, mut callback: F) -> Option<CallbackHandle>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+        F: FnMut(&V) + Send + 'static,
+    {
+        self.on_change_async(key, move |value| {
+            callback(&value);
+            future::ready(())
+        })
+        .await
+    }
+
+    /// Like [`SubscriptionMap::on_change`], but awaits the future returned by the callback before
+    /// the next value is handed to it, so slow callbacks never overlap.
+    pub async fn on_change_async<Q, F, Fut>(&self, key: &Q, callback: F) -> Option<CallbackHandle>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+        F: FnMut(V) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        // only values published after registering are handed to the callback
+        let subscription = self.subscribe_with(key, Replay::Skip).await?;
+        let (task, handle) = future::abortable(run(subscription, callback));
+        let finished = handle.clone();
+
+        runtime::spawn(async move {
+            let _ = task.await;
+            // marks the handle as finished if the entry is gone
+            finished.abort();
+        });
+
+        Some(CallbackHandle { handle })
+    }
+}
+
+async fn run<K, V, F, Fut>(mut subscription: SubscriptionRef<K, V>, mut callback: F)
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+    F: FnMut(V) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    while let Ok(value) = subscription.next_or_closed().await {
+        callback(value).await;
+    }
+
+    log::trace!("callback of key {:?} stopped", subscription.key());
+}
@@ -0,0 +1,235 @@
+use crate::backend::{MapBackend, Storage};
+use crate::keys::is_empty_range;
+use crate::{MapEvent, SubscriptionMap};
+use async_observable::Observable;
+use futures::stream::{self, AbortHandle, BoxStream, SelectAll};
+use futures::{future, Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::{Bound, RangeBounds};
+
+/// Items multiplexed by the fan-in stream
+enum Change<K, V> {
+    Published(K, V),
+    /// The key was inserted into or removed from the map
+    Membership(K),
+}
+
+/// Fan-in over the observables of all entries of a map matching a filter
+struct AllChanges<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    map: SubscriptionMap<K, V>,
+    /// Only entries whose key matches are watched
+    filter: Box<dyn Fn(&K) -> bool + Send + Sync>,
+    /// Entry id and handle to stop the stream of every watched key
+    watched: BTreeMap<K, (u64, AbortHandle)>,
+    streams: SelectAll<BoxStream<'static, Change<K, V>>>,
+}
+
+impl<K, V> AllChanges<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    /// Watch the entries matching the filter, all of which must lie within the bounds. Ordered
+    /// storage only looks at the keys from the lower bound up to the first one not matching.
+    async fn new(
+        map: SubscriptionMap<K, V>,
+        bounds: (Bound<K>, Bound<K>),
+        filter: Box<dyn Fn(&K) -> bool + Send + Sync>,
+    ) -> Self {
+        let mut changes = Self {
+            map,
+            filter,
+            watched: BTreeMap::new(),
+            streams: SelectAll::new(),
+        };
+
+        let shards = changes.map.0.read_all().await;
+        // listening under the lock, so no entry is missed
+        let events = changes.map.0.events().listen();
+
+        let mut entries = Vec::new();
+
+        for state in shards.iter() {
+            let matching: Box<dyn Iterator<Item = _>> = match &state.entries {
+                Storage::Ordered(_) if is_empty_range(&bounds.0, &bounds.1) => {
+                    Box::new(std::iter::empty())
+                }
+                Storage::Ordered(entries) => Box::new(
+                    entries
+                        .range(bounds.clone())
+                        .take_while(|(key, _)| (changes.filter)(key)),
+                ),
+                Storage::Hashed(entries) => {
+                    Box::new(entries.iter().filter(|(key, _)| (changes.filter)(key)))
+                }
+            };
+
+            entries
+                .extend(matching.map(|(key, entry)| {
+                    (key.clone(), entry.id, synchronized(&entry.observable).0)
+                }));
+        }
+
+        drop(shards);
+
+        changes.streams.push(
+            events
+                .filter_map(|event| {
+                    future::ready(match event {
+                        MapEvent::Inserted(key) | MapEvent::Removed(key) => {
+                            Some(Change::Membership(key))
+                        }
+                        MapEvent::FirstSubscriber(_) | MapEvent::LastUnsubscribed(_) => None,
+                    })
+                })
+                .boxed(),
+        );
+
+        for (key, id, observable) in entries {
+            changes.watch(key, id, observable, None);
+        }
+
+        changes
+    }
+
+    /// Stream the changes of the entry, starting with the initial value if given
+    fn watch(&mut self, key: K, id: u64, observable: Observable<V>, initial: Option<V>) {
+        let initial = initial.map(|value| Change::Published(key.clone(), value));
+        let published = stream::unfold(observable, {
+            let key = key.clone();
+            move |mut observable| {
+                let key = key.clone();
+                async move {
+                    let value = observable.next().await;
+                    Some((Change::Published(key, value), observable))
+                }
+            }
+        });
+
+        let (published, handle) = stream::abortable(stream::iter(initial).chain(published));
+        self.streams.push(published.boxed());
+        self.watched.insert(key, (id, handle));
+    }
+
+    /// Start watching the entry of a key if it was inserted, or stop if it was removed. Only the
+    /// shard of the key is looked at.
+    async fn sync(&mut self, key: K) {
+        let present = {
+            let state = self.map.0.for_key(&key).read().await;
+            let entry = state.entries.get(&key);
+            entry.map(|entry| (entry.id, synchronized(&entry.observable)))
+        };
+
+        if let Some(((watched, _), (id, _))) = self.watched.get(&key).zip(present.as_ref()) {
+            if watched == id {
+                return;
+            }
+        }
+
+        // removed, or replaced by another entry under the same key
+        if let Some((_, handle)) = self.watched.remove(&key) {
+            handle.abort();
+        }
+
+        // emit the value the entry was inserted with, which was never published
+        if let Some((id, (observable, value))) = present {
+            self.watch(key, id, observable, Some(value));
+        }
+    }
+
+    async fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            match self.streams.next().await? {
+                Change::Published(key, value) => return Some((key, value)),
+                Change::Membership(key) if (self.filter)(&key) => self.sync(key).await,
+                Change::Membership(_) => {}
+            }
+        }
+    }
+}
+
+/// Fork the observable at its latest version, along with the latest value
+///
+/// Observables owned by the map never publish themselves, so their own version is stale and a
+/// plain fork would yield a change right away.
+fn synchronized<T>(observable: &Observable<T>) -> (Observable<T>, T)
+where
+    T: Clone,
+{
+    let mut fork = observable.fork();
+    let value = fork.synchronize();
+    (fork, value)
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    /// A single stream of every change across the whole map.
+    ///
+    /// Changes of all entries present when calling this method are emitted, as well as the
+    /// initial value and all changes of entries inserted later on. Entries removed from the map
+    /// simply stop emitting. Like with a single observable, rapid changes of one entry might be
+    /// coalesced into the latest value.
+    ///
+    /// The stream doesn't hold any subscriptions, so it doesn't keep entries alive.
+    pub async fn all_changes(&self) -> impl Stream<Item = (K, V)> {
+        self.changes_matching((Bound::Unbounded, Bound::Unbounded), Box::new(|_| true))
+            .await
+    }
+
+    /// Like [`SubscriptionMap::all_changes`] but only for entries whose key is within the range,
+    /// including entries inserted into the range later on.
+    ///
+    /// Ordered maps only look at the keys within the range when the stream is created, entries
+    /// inserted or removed later on are only looked up if their key is within the range.
+    pub async fn subscribe_range<R>(&self, range: R) -> impl Stream<Item = (K, V)>
+    where
+        R: RangeBounds<K> + Send + Sync + 'static,
+    {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        self.changes_matching(bounds, Box::new(move |key| range.contains(key)))
+            .await
+    }
+
+    async fn changes_matching(
+        &self,
+        bounds: (Bound<K>, Bound<K>),
+        filter: Box<dyn Fn(&K) -> bool + Send + Sync>,
+    ) -> impl Stream<Item = (K, V)> {
+        let changes = AllChanges::new(self.clone(), bounds, filter).await;
+
+        stream::unfold(changes, |mut changes| async move {
+            let change = changes.next().await?;
+            Some((change, changes))
+        })
+    }
+}
+
+impl<S, V> SubscriptionMap<Vec<S>, V>
+where
+    S: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + 'static,
+{
+    /// Treat keys as paths of segments and stream the changes of all entries at or below the
+    /// prefix, e.g. `["sensors", "room1"]` covers `["sensors", "room1", "temperature"]`. Entries
+    /// created below the prefix later on are included, see [`SubscriptionMap::all_changes`].
+    ///
+    /// An empty prefix covers the whole map. Keys below a prefix directly follow it in order, so
+    /// ordered maps only look at those.
+    pub async fn subscribe_subtree(&self, prefix: Vec<S>) -> impl Stream<Item = (Vec<S>, V)> {
+        self.changes_matching(
+            (Bound::Included(prefix.clone()), Bound::Unbounded),
+            Box::new(move |key: &Vec<S>| key.starts_with(&prefix)),
+        )
+        .await
+    }
+}
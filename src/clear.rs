@@ -0,0 +1,71 @@
+use crate::backend::MapBackend;
+use crate::{SubscriptionEntry, SubscriptionMap};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// How to treat entries still in use when clearing a map, see [`SubscriptionMap::clear_with`].
+/// Entries are in use while they have subscribers or are pinned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClearMode {
+    /// Don't remove anything if any entry is in use
+    Fail,
+    /// Only remove the entries which aren't in use
+    Skip,
+    /// Remove all entries, subscribers are notified like for [`SubscriptionMap::evict`]
+    Evict,
+}
+
+fn in_use<K, V>(entry: &SubscriptionEntry<K, V>) -> bool
+where
+    V: Clone,
+{
+    !entry.rc.is_zero() || entry.pins > 0
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// Remove the entries of the map, the mode decides what happens to entries still in use.
+    /// Returns the number of removed entries, persistent entries are removed like all others.
+    ///
+    /// All shards are locked at once, so the map is cleared atomically. Fails only with
+    /// [`ClearMode::Fail`], if any entry is in use.
+    pub async fn clear_with(&self, mode: ClearMode) -> anyhow::Result<usize> {
+        let mut shards = self.0.write_all().await;
+
+        if mode == ClearMode::Fail {
+            let used: Vec<&K> = shards
+                .iter()
+                .flat_map(|state| state.entries.iter())
+                .filter(|(_, entry)| in_use(entry))
+                .map(|(key, _)| key)
+                .collect();
+
+            anyhow::ensure!(
+                used.is_empty(),
+                "unable to clear map with keys in use {:?}",
+                used
+            );
+        }
+
+        let mut removed = 0;
+
+        for state in shards.iter_mut() {
+            let doomed: Vec<K> = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| mode == ClearMode::Evict || !in_use(entry))
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for key in doomed {
+                state.evict(&key);
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+/// Coalesces rapid publishes of a single entry, so that at most one value per interval is emitted
+/// to subscribers. Values published within the interval are kept as pending value, which is
+/// emitted by the next flush.
+///
+/// In quiet mode every value is kept as pending value instead, which is only emitted once no
+/// other value was published for the interval.
+#[derive(Clone, Debug)]
+pub(crate) struct Debounce<V>
+where
+    V: Clone,
+{
+    interval: Duration,
+    /// Emit only after the interval passed without publishes, rather than at most once per
+    /// interval
+    quiet: bool,
+    /// When a value was emitted last, or published last in quiet mode
+    last_emit: Option<Instant>,
+    pending: Option<V>,
+}
+
+impl<V> Debounce<V>
+where
+    V: Clone,
+{
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            quiet: false,
+            last_emit: None,
+            pending: None,
+        }
+    }
+
+    /// Debounce in quiet mode, see [`Debounce`]
+    pub fn quiet(interval: Duration) -> Self {
+        Self {
+            quiet: true,
+            ..Self::new(interval)
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Time left until the next value may be emitted
+    fn remaining(&self) -> Duration {
+        self.last_emit
+            .map(|last| self.interval.saturating_sub(last.elapsed()))
+            .unwrap_or_default()
+    }
+
+    /// The pending value, which will be emitted by the next flush
+    pub fn pending(&self) -> Option<&V> {
+        self.pending.as_ref()
+    }
+
+    /// Drop the pending value, e.g. because the published value was reverted
+    pub fn discard(&mut self) {
+        self.pending = None;
+    }
+
+    /// Return the value if it may be emitted right away, otherwise keep it as pending value
+    pub fn admit(&mut self, value: V) -> Option<V> {
+        if self.quiet {
+            self.last_emit = Some(Instant::now());
+            self.pending = Some(value);
+            return None;
+        }
+
+        if self.remaining().is_zero() {
+            self.pending = None;
+            self.last_emit = Some(Instant::now());
+            Some(value)
+        } else {
+            self.pending = Some(value);
+            None
+        }
+    }
+
+    /// Keep a flushed value pending for the next flush, e.g. because there's no room for it yet
+    pub fn defer(&mut self, value: V) {
+        self.pending = Some(value);
+    }
+
+    /// Return the pending value if the interval elapsed, together with the time until the next
+    /// flush is due
+    pub fn flush(&mut self) -> (Option<V>, Duration) {
+        let remaining = self.remaining();
+
+        if !remaining.is_zero() {
+            return (None, remaining);
+        }
+
+        let value = self.pending.take();
+
+        if value.is_some() && !self.quiet {
+            self.last_emit = Some(Instant::now());
+        }
+
+        (value, self.interval)
+    }
+}
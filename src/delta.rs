@@ -0,0 +1,197 @@
+use crate::backend::MapBackend;
+use crate::{EntryState, SubscriptionMap, SubscriptionRef};
+use anyhow::Context;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::Stream;
+use std::any::Any;
+use std::borrow::Borrow;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+
+/// A value which can be patched with deltas of type `D`, see
+/// [`SubscriptionMap::publish_delta`]
+pub trait ApplyDelta<D> {
+    fn apply_delta(&mut self, delta: &D);
+}
+
+/// A change received by a [`DeltaSubscription`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Update<V, D> {
+    /// The whole value, sent when subscribing and for every publish which wasn't a delta
+    Full(V),
+    /// A delta to apply to the previously received value
+    Delta(D),
+}
+
+type Sink<V> = Box<dyn FnMut(&V, Option<&dyn Any>) -> bool + Send>;
+
+/// The delta subscribers of an entry, every emitted value is handed to all of them
+///
+/// Subscribers are type erased, as the type of their deltas isn't part of the entry type. A delta
+/// of a different type than the subscriber expects is sent as full value instead. Deltas are
+/// delivered through unbounded channels, so none of them get lost, and subscribers are dropped as
+/// soon as their receiving end is gone.
+pub(crate) struct DeltaSinks<V>(Vec<Sink<V>>);
+
+impl<V> DeltaSinks<V>
+where
+    V: Clone,
+{
+    pub fn add<D>(&mut self, sender: UnboundedSender<Update<V, D>>)
+    where
+        V: Send + 'static,
+        D: Clone + Send + 'static,
+    {
+        self.0.push(Box::new(move |value, delta| {
+            let update = match delta.and_then(|delta| delta.downcast_ref::<D>()) {
+                Some(delta) => Update::Delta(delta.clone()),
+                None => Update::Full(value.clone()),
+            };
+
+            sender.unbounded_send(update).is_ok()
+        }));
+    }
+
+    /// Hand the emitted value, together with the delta it resulted from, to all subscribers
+    pub fn send(&mut self, value: &V, delta: Option<&dyn Any>) {
+        self.0.retain_mut(|sink| sink(value, delta));
+    }
+}
+
+impl<V> Default for DeltaSinks<V> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<V> Debug for DeltaSinks<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DeltaSinks").field(&self.0.len()).finish()
+    }
+}
+
+/// A subscription receiving deltas instead of full values where possible, see
+/// [`SubscriptionMap::subscribe_deltas`].
+///
+/// Unlike a [`SubscriptionRef`], which only sees the latest value, every update is received in
+/// order, so applying the deltas to the initial full value reproduces the value of the entry. The
+/// stream ends once the entry is removed from the map.
+pub struct DeltaSubscription<K, V, D>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    subscription: SubscriptionRef<K, V>,
+    updates: UnboundedReceiver<Update<V, D>>,
+}
+
+impl<K, V, D> DeltaSubscription<K, V, D>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// The key of the subscribed entry
+    pub fn key(&self) -> K {
+        self.subscription.key()
+    }
+
+    /// Give up the deltas and get back a regular subscription to the entry
+    pub fn into_inner(self) -> SubscriptionRef<K, V> {
+        self.subscription
+    }
+}
+
+// no field is structurally pinned
+impl<K, V, D> Unpin for DeltaSubscription<K, V, D>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+}
+
+impl<K, V, D> Stream for DeltaSubscription<K, V, D>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    type Item = Update<V, D>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.updates).poll_next(cx)
+    }
+}
+
+impl<K, V, D> Debug for DeltaSubscription<K, V, D>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeltaSubscription")
+            .field("key", &self.subscription.key())
+            .finish()
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone + Send + 'static,
+{
+    /// Apply the delta to the value of the entry and publish the result. Regular subscribers
+    /// receive the patched value, while subscribers of deltas of the same type, see
+    /// [`SubscriptionMap::subscribe_deltas`], receive just the delta.
+    ///
+    /// Debounced entries emit the patched value later, delta subscribers receive it as full
+    /// value then. Returns the number of live subscribers of the entry.
+    pub async fn publish_delta<D, Q>(&self, key: &Q, delta: D) -> anyhow::Result<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        V: ApplyDelta<D>,
+        D: Send + 'static,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable publish delta to not present key {:?}", key))?;
+
+        let mut state = EntryState::lock_admitted(&state).await?;
+        state.publish_delta(&delta);
+
+        Ok(state.subscribers())
+    }
+
+    /// Subscribe to the entry, receiving its current value first and the deltas published via
+    /// [`SubscriptionMap::publish_delta`] afterwards. Values published otherwise, and deltas of
+    /// other types, are received as full values. Returns `None` if the key isn't present.
+    pub async fn subscribe_deltas<D, Q>(&self, key: &Q) -> Option<DeltaSubscription<K, V, D>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+        D: Clone + Send + 'static,
+    {
+        // subscribed and sent to the same entry, even if the key is replaced in between
+        let (subscription, state) = {
+            let state = self.0.for_key(key).read().await;
+            let entry = state.entries.get(key)?;
+            let subscription = SubscriptionRef::new(self.clone(), entry).unwrap();
+
+            (subscription, entry.state.clone())
+        };
+        let mut state = state.lock().await;
+
+        let (tx, updates) = mpsc::unbounded();
+        // sent under the entry lock, so no delta can slip in between
+        tx.unbounded_send(Update::Full(state.observable.latest()))
+            .expect("receiver is alive");
+        state.deltas.add(tx);
+
+        Some(DeltaSubscription {
+            subscription,
+            updates,
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides whether a published value differs enough from the current one to be handed to
+/// subscribers, see
+/// [`SubscriptionMapBuilder::change_detect`](crate::SubscriptionMapBuilder::change_detect).
+///
+/// Implemented for closures taking the current and the new value, so semantic equality, like
+/// ignoring timestamps or comparing within a tolerance, can be defined once per map.
+pub trait ChangeDetect<V>: Send + Sync {
+    /// Whether `new` is a change compared to `current`
+    fn is_changed(&self, current: &V, new: &V) -> bool;
+}
+
+impl<V, F> ChangeDetect<V> for F
+where
+    F: Fn(&V, &V) -> bool + Send + Sync,
+{
+    fn is_changed(&self, current: &V, new: &V) -> bool {
+        self(current, new)
+    }
+}
+
+/// The change detection configured for a map, falls back to `PartialEq` if there is none
+pub(crate) struct Detector<V>(Option<Arc<dyn ChangeDetect<V>>>);
+
+impl<V> Detector<V>
+where
+    V: PartialEq,
+{
+    pub fn is_changed(&self, current: &V, new: &V) -> bool {
+        match &self.0 {
+            Some(detect) => detect.is_changed(current, new),
+            None => current != new,
+        }
+    }
+}
+
+impl<V> Detector<V> {
+    pub fn new(detect: impl ChangeDetect<V> + 'static) -> Self {
+        Self(Some(Arc::new(detect)))
+    }
+}
+
+impl<V> Clone for Detector<V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<V> Default for Detector<V> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<V> fmt::Debug for Detector<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Detector(custom)"),
+            None => f.write_str("Detector(PartialEq)"),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::SubscriptionMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// The differences between a previous snapshot of a map and its current state, see
+/// [`SubscriptionMap::diff`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapDiff<K, V>
+where
+    K: Ord,
+{
+    added: BTreeMap<K, V>,
+    removed: BTreeSet<K>,
+    changed: BTreeMap<K, V>,
+}
+
+impl<K, V> MapDiff<K, V>
+where
+    K: Clone + Ord,
+    V: Clone,
+{
+    /// Keys missing in the previous snapshot, with their current values
+    pub fn added(&self) -> &BTreeMap<K, V> {
+        &self.added
+    }
+
+    /// Keys of the previous snapshot which aren't present anymore
+    pub fn removed(&self) -> &BTreeSet<K> {
+        &self.removed
+    }
+
+    /// Keys whose values changed since the previous snapshot, with their current values
+    pub fn changed(&self) -> &BTreeMap<K, V> {
+        &self.changed
+    }
+
+    /// Check if the map didn't change at all
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Update the previous snapshot to the state the diff was taken at, e.g. to diff against it
+    /// again later on
+    pub fn apply_to(&self, snapshot: &mut BTreeMap<K, V>) {
+        for key in &self.removed {
+            snapshot.remove(key);
+        }
+
+        for (key, value) in self.added.iter().chain(&self.changed) {
+            snapshot.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone + PartialEq,
+{
+    /// Compare a previous snapshot, see [`SubscriptionMap::snapshot`], with the current state of
+    /// the map. Values are compared with the change detection of the map, see
+    /// [`SubscriptionMapBuilder::change_detect`](crate::SubscriptionMapBuilder::change_detect).
+    ///
+    /// Useful to bring late joining clients up to date, which only know an older snapshot.
+    pub async fn diff(&self, older: &BTreeMap<K, V>) -> MapDiff<K, V> {
+        let current = self.snapshot().await;
+        let detect = self.detector().await;
+
+        let mut diff = MapDiff {
+            added: BTreeMap::new(),
+            removed: older
+                .keys()
+                .filter(|key| !current.contains_key(key))
+                .cloned()
+                .collect(),
+            changed: BTreeMap::new(),
+        };
+
+        for (key, value) in current {
+            match older.get(&key) {
+                None => {
+                    diff.added.insert(key, value);
+                }
+                Some(old) if detect.is_changed(old, &value) => {
+                    diff.changed.insert(key, value);
+                }
+                Some(_) => {}
+            }
+        }
+
+        diff
+    }
+}
@@ -0,0 +1,170 @@
+use crate::backend::MapBackend;
+use crate::{
+    expect_inserted, InsertError, MapState, PublishError, SubscriptionEntry, SubscriptionMap,
+    SubscriptionRef,
+};
+use async_lock::RwLockWriteGuard;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// A view into a single key of the map, see [`SubscriptionMap::entry`].
+///
+/// The map, or the shard of the key, stays locked for writing while the entry is held, so
+/// inserting, publishing and subscribing through it can't race with other tasks. The entry should
+/// be dropped as soon as possible.
+#[derive(Debug)]
+pub enum Entry<'a, K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// The key is present in the map
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// The key isn't present in the map
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// A present key of the map, see [`Entry`]
+#[derive(Debug)]
+pub struct OccupiedEntry<'a, K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    key: K,
+    map: &'a SubscriptionMap<K, V>,
+    state: RwLockWriteGuard<'a, MapState<K, V>>,
+}
+
+/// A missing key of the map, see [`Entry`]
+#[derive(Debug)]
+pub struct VacantEntry<'a, K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    key: K,
+    map: &'a SubscriptionMap<K, V>,
+    state: RwLockWriteGuard<'a, MapState<K, V>>,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub(crate) fn new(
+        key: K,
+        map: &'a SubscriptionMap<K, V>,
+        state: RwLockWriteGuard<'a, MapState<K, V>>,
+    ) -> Self {
+        if state.entries.contains_key(&key) {
+            Entry::Occupied(OccupiedEntry { key, map, state })
+        } else {
+            Entry::Vacant(VacantEntry { key, map, state })
+        }
+    }
+
+    /// The key of the entry
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Subscribe to the entry, inserting it with the given value if missing
+    pub fn or_insert(self, value: V) -> SubscriptionRef<K, V> {
+        self.or_insert_with(|| value)
+    }
+
+    /// Subscribe to the entry, inserting it with the value returned by the closure if missing
+    pub fn or_insert_with<F>(self, value: F) -> SubscriptionRef<K, V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(entry) => entry.subscribe(),
+            Entry::Vacant(entry) => entry.insert(value()),
+        }
+    }
+
+    /// Publish the value if the entry is present, see [`OccupiedEntry::publish`]
+    pub async fn and_publish(self, value: V) -> Result<Self, PublishError> {
+        match self {
+            Entry::Occupied(entry) => {
+                entry.publish(value).await?;
+                Ok(Entry::Occupied(entry))
+            }
+            vacant => Ok(vacant),
+        }
+    }
+}
+
+impl<K, V> OccupiedEntry<'_, K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// The key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The most recent value of the entry
+    pub async fn get(&self) -> V {
+        self.entry().state.lock().await.latest()
+    }
+
+    /// The number of live subscription refs of the entry
+    pub fn subscribers(&self) -> usize {
+        self.entry().rc.get()
+    }
+
+    /// Publish the value to the entry, like [`SubscriptionMap::publish`]. Fails if the entry
+    /// refuses the value, e.g. because it exceeded its rate limit, or a bounded subscriber has no
+    /// room for it, as waiting for room would keep the map locked.
+    pub async fn publish(&self, value: V) -> Result<(), PublishError> {
+        self.entry().state.lock().await.try_publish(value)
+    }
+
+    /// Subscribe to the entry
+    pub fn subscribe(&self) -> SubscriptionRef<K, V> {
+        SubscriptionRef::new(self.map.clone(), self.entry()).unwrap()
+    }
+
+    fn entry(&self) -> &SubscriptionEntry<K, V> {
+        self.state
+            .entries
+            .get(&self.key)
+            .expect("occupied entries are present while locked")
+    }
+}
+
+impl<K, V> VacantEntry<'_, K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// The key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Insert the entry with the given value and subscribe to it
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, like
+    /// [`SubscriptionMap::get_or_insert`].
+    pub fn insert(self, value: V) -> SubscriptionRef<K, V> {
+        expect_inserted(self.try_insert(value))
+    }
+
+    /// Like [`VacantEntry::insert`] but returns an error instead of panicking if the entry can't be
+    /// inserted, because the map is closed or full.
+    pub fn try_insert(mut self, value: V) -> Result<SubscriptionRef<K, V>, InsertError> {
+        let entry = self.state.try_get_or_insert_with(self.key, || value)?;
+        Ok(SubscriptionRef::new(self.map.clone(), entry).unwrap())
+    }
+}
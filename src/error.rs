@@ -0,0 +1,271 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// The map could not be locked within the given duration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeoutError {
+    duration: Duration,
+}
+
+impl TimeoutError {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self { duration }
+    }
+
+    /// The duration after which the operation gave up
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unable to lock subscription map within {:?}",
+            self.duration
+        )
+    }
+}
+
+impl Error for TimeoutError {}
+
+/// A subscription ref didn't receive a matching value within the given duration, see
+/// [`SubscriptionRef::next_timeout`](crate::SubscriptionRef::next_timeout)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed {
+    duration: Duration,
+}
+
+impl Elapsed {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self { duration }
+    }
+
+    /// The duration after which waiting gave up
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no value received within {:?}", self.duration)
+    }
+}
+
+impl Error for Elapsed {}
+
+/// The map is full and configured to reject new entries, see
+/// [`SubscriptionMapBuilder::capacity`](crate::SubscriptionMapBuilder::capacity)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CapacityError {
+    capacity: usize,
+}
+
+impl CapacityError {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { capacity }
+    }
+
+    /// The maximum number of entries the map, or its shard, can hold
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subscription map is full with {} entries", self.capacity)
+    }
+}
+
+impl Error for CapacityError {}
+
+/// An entry couldn't be inserted by one of the `try_` methods, like
+/// [`SubscriptionMap::try_get_or_insert`](crate::SubscriptionMap::try_get_or_insert)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertError {
+    /// The map is full and can't make room for the entry
+    Full(CapacityError),
+    /// The map was shut down, see [`SubscriptionMap::close`](crate::SubscriptionMap::close)
+    Closed(ClosedError),
+    /// The map couldn't be locked in time, only returned by
+    /// [`SubscriptionMap::try_get_or_insert_timeout`](crate::SubscriptionMap::try_get_or_insert_timeout)
+    Timeout(TimeoutError),
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertError::Full(e) => e.fmt(f),
+            InsertError::Closed(_) => write!(f, "unable to insert into closed subscription map"),
+            InsertError::Timeout(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for InsertError {}
+
+impl From<CapacityError> for InsertError {
+    fn from(e: CapacityError) -> Self {
+        InsertError::Full(e)
+    }
+}
+
+impl From<ClosedError> for InsertError {
+    fn from(e: ClosedError) -> Self {
+        InsertError::Closed(e)
+    }
+}
+
+impl From<TimeoutError> for InsertError {
+    fn from(e: TimeoutError) -> Self {
+        InsertError::Timeout(e)
+    }
+}
+
+/// Publishing was refused, because the entry exceeded its rate limit, see
+/// [`SubscriptionMapBuilder::rate_limit`](crate::SubscriptionMapBuilder::rate_limit)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitedError {
+    retry_after: Duration,
+}
+
+impl RateLimitedError {
+    pub(crate) fn new(retry_after: Duration) -> Self {
+        Self { retry_after }
+    }
+
+    /// The time until the entry accepts the next publish
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+}
+
+impl fmt::Display for RateLimitedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "subscription map entry exceeded its rate limit, retry after {:?}",
+            self.retry_after
+        )
+    }
+}
+
+impl Error for RateLimitedError {}
+
+/// An entry refused to publish a value, reported by the methods which don't return an
+/// `anyhow::Result`, like [`SubscriptionMap::publish_batch`](crate::SubscriptionMap::publish_batch)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishError {
+    /// The entry exceeded its rate limit, see
+    /// [`SubscriptionMapBuilder::rate_limit`](crate::SubscriptionMapBuilder::rate_limit)
+    RateLimited(RateLimitedError),
+    /// The buffer of a bounded subscriber is full, see
+    /// [`SubscriptionMap::subscribe_bounded`](crate::SubscriptionMap::subscribe_bounded)
+    BufferFull(BufferFullError),
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::RateLimited(e) => e.fmt(f),
+            PublishError::BufferFull(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for PublishError {}
+
+impl From<RateLimitedError> for PublishError {
+    fn from(e: RateLimitedError) -> Self {
+        PublishError::RateLimited(e)
+    }
+}
+
+impl From<BufferFullError> for PublishError {
+    fn from(e: BufferFullError) -> Self {
+        PublishError::BufferFull(e)
+    }
+}
+
+/// Publishing was refused, because the buffer of a bounded subscriber is full, see
+/// [`SubscriptionMap::subscribe_bounded`](crate::SubscriptionMap::subscribe_bounded)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferFullError {
+    capacity: usize,
+}
+
+impl BufferFullError {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { capacity }
+    }
+
+    /// The capacity of the full buffer
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl fmt::Display for BufferFullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subscriber buffer of capacity {} is full", self.capacity)
+    }
+}
+
+impl Error for BufferFullError {}
+
+/// A [`SubscriptionMap::compare_and_publish`](crate::SubscriptionMap::compare_and_publish) which
+/// didn't publish, because another writer got there first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CasError<V> {
+    /// The current value differs from the expected one
+    Mismatch(V),
+    /// The key isn't present in the map
+    Absent,
+    /// The expected value matched, but the entry refused the new one
+    Refused(PublishError),
+}
+
+impl<V> CasError<V> {
+    /// The actual current value, if the key is present
+    pub fn into_actual(self) -> Option<V> {
+        match self {
+            CasError::Mismatch(actual) => Some(actual),
+            CasError::Absent | CasError::Refused(_) => None,
+        }
+    }
+}
+
+impl<V> fmt::Display for CasError<V>
+where
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CasError::Mismatch(actual) => {
+                write!(f, "expected value differs from current value {:?}", actual)
+            }
+            CasError::Absent => write!(f, "unable to compare and publish to not present key"),
+            CasError::Refused(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<V> Error for CasError<V> where V: fmt::Debug {}
+
+/// The entry of a subscription is gone for good, because it was evicted or the map was closed,
+/// see [`SubscriptionRef::next_or_closed`](crate::SubscriptionRef::next_or_closed) and
+/// [`SubscriptionMap::wait_for`](crate::SubscriptionMap::wait_for)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClosedError;
+
+impl fmt::Display for ClosedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subscription map entry was evicted or the map closed")
+    }
+}
+
+impl Error for ClosedError {}
@@ -0,0 +1,83 @@
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// A change in the lifecycle of an entry, see
+/// [`SubscriptionMap::events`](crate::SubscriptionMap::events)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapEvent<K> {
+    /// The entry was inserted into the map
+    Inserted(K),
+    /// The entry was removed from the map, either because it became unused or was evicted
+    Removed(K),
+    /// The entry gained its first subscription ref
+    FirstSubscriber(K),
+    /// The last subscription ref of the entry was dropped
+    LastUnsubscribed(K),
+}
+
+/// A key entering or leaving the map, see
+/// [`SubscriptionMap::key_stream`](crate::SubscriptionMap::key_stream)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyEvent<K> {
+    /// The key was inserted into the map, or was present when the stream started
+    Inserted(K),
+    /// The key was removed from the map
+    Removed(K),
+}
+
+impl<K> KeyEvent<K> {
+    /// The membership change of a lifecycle event, if it is one
+    pub(crate) fn from_event(event: MapEvent<K>) -> Option<Self> {
+        match event {
+            MapEvent::Inserted(key) => Some(KeyEvent::Inserted(key)),
+            MapEvent::Removed(key) => Some(KeyEvent::Removed(key)),
+            MapEvent::FirstSubscriber(_) | MapEvent::LastUnsubscribed(_) => None,
+        }
+    }
+}
+
+/// The listeners of a map's lifecycle events, shared by all of its shards
+///
+/// Events are delivered through unbounded channels, so emitting never waits for slow listeners.
+/// Listeners are dropped as soon as their receiving end is gone.
+#[derive(Debug)]
+pub(crate) struct Events<K>(Arc<Mutex<Vec<UnboundedSender<MapEvent<K>>>>>);
+
+impl<K> Events<K>
+where
+    K: Clone + Debug,
+{
+    pub fn listen(&self) -> UnboundedReceiver<MapEvent<K>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.listeners().push(tx);
+        rx
+    }
+
+    pub fn emit(&self, event: MapEvent<K>) {
+        let mut listeners = self.listeners();
+
+        if listeners.is_empty() {
+            return;
+        }
+
+        log::trace!("emitting {:?}", event);
+        listeners.retain(|listener| listener.unbounded_send(event.clone()).is_ok());
+    }
+
+    fn listeners(&self) -> std::sync::MutexGuard<'_, Vec<UnboundedSender<MapEvent<K>>>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K> Clone for Events<K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K> Default for Events<K> {
+    fn default() -> Self {
+        Self(Arc::default())
+    }
+}
@@ -0,0 +1,134 @@
+use crate::SubscriptionMap;
+use futures::stream::{self, AbortHandle, Abortable, BoxStream, SelectAll};
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+
+/// Subscriptions to a changing set of keys combined into a single stream of changes, see
+/// [`SubscriptionMap::group`].
+///
+/// The group holds one subscription ref per key and releases it as soon as the key is removed
+/// from the group, so only the entries currently in the group are kept alive.
+pub struct GroupSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    map: SubscriptionMap<K, V>,
+    /// Handle to stop the stream of every member, which owns its subscription ref
+    members: BTreeMap<K, AbortHandle>,
+    changes: SelectAll<Abortable<BoxStream<'static, (K, V)>>>,
+}
+
+impl<K, V> GroupSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn new(map: SubscriptionMap<K, V>) -> Self {
+        let mut changes = SelectAll::new();
+        // keeps the combined stream running while the group is empty
+        changes.push(stream::abortable(stream::pending().boxed()).0);
+
+        Self {
+            map,
+            members: BTreeMap::new(),
+            changes,
+        }
+    }
+
+    /// Add the key to the group and return whether it was added. Like with
+    /// [`SubscriptionMap::get`], keys which aren't present in the map aren't added.
+    ///
+    /// The current value of the entry is yielded right away by the next call to
+    /// [`GroupSubscription::next`].
+    pub async fn add(&mut self, key: K) -> bool {
+        if self.members.contains_key(&key) {
+            return false;
+        }
+
+        let subscription = match self.map.get(&key).await {
+            Some(subscription) => subscription,
+            None => return false,
+        };
+
+        // driven through the ref, so the lag of the member is tracked like for any subscriber
+        let values = stream::unfold(subscription, {
+            let key = key.clone();
+            move |mut subscription| {
+                let key = key.clone();
+                async move {
+                    let value = subscription.next().await;
+                    Some(((key, value), subscription))
+                }
+            }
+        });
+
+        let (values, handle) = stream::abortable(values.boxed());
+        self.changes.push(values);
+        self.members.insert(key, handle);
+
+        true
+    }
+
+    /// Remove the key from the group, releasing its subscription, and return whether it was a
+    /// member. Changes of the entry which weren't yielded yet are dropped.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(handle) = self.members.remove(key) else {
+            return false;
+        };
+
+        // drop the stream right away instead of on its next poll, releasing the subscription
+        handle.abort();
+        self.changes = std::mem::take(&mut self.changes)
+            .into_iter()
+            .filter(|values| !values.is_aborted())
+            .collect();
+
+        true
+    }
+
+    /// Check if the key is a member of the group
+    pub fn contains(&self, key: &K) -> bool {
+        self.members.contains_key(key)
+    }
+
+    /// The keys currently in the group, in ascending order
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.members.keys()
+    }
+
+    /// Wait until any entry of the group changes. While the group is empty this waits until a
+    /// key is added and changes.
+    pub async fn next(&mut self) -> (K, V) {
+        self.changes
+            .next()
+            .await
+            .expect("the combined stream never ends")
+    }
+}
+
+impl<K, V> Debug for GroupSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupSubscription")
+            .field("keys", &self.members.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Create an empty group of subscriptions whose keys can be changed while its combined
+    /// stream of changes keeps running, e.g. for clients changing their watch list.
+    pub fn group(&self) -> GroupSubscription<K, V> {
+        GroupSubscription::new(self.clone())
+    }
+}
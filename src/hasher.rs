@@ -0,0 +1,136 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hasher};
+
+/// The hash function of hashed storage and shard selection, see
+/// [`SubscriptionMapBuilder::hasher`](crate::SubscriptionMapBuilder::hasher)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyHashing {
+    /// The randomly seeded SipHash of the std `HashMap`, resistant against keys chosen to collide
+    #[default]
+    Std,
+    /// The FxHash of the Rust compiler, considerably faster but only suited for trusted keys
+    Fx,
+}
+
+/// Builds the hashers of the configured [`KeyHashing`], which are all known up front, so hashing
+/// doesn't allocate or go through a trait object
+#[derive(Clone, Debug)]
+pub(crate) enum KeyHasher {
+    Std(RandomState),
+    Fx,
+}
+
+impl KeyHasher {
+    pub fn new(hashing: KeyHashing) -> Self {
+        match hashing {
+            KeyHashing::Std => Self::Std(RandomState::new()),
+            KeyHashing::Fx => Self::Fx,
+        }
+    }
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        Self::new(KeyHashing::default())
+    }
+}
+
+impl BuildHasher for KeyHasher {
+    type Hasher = KeyHasherState;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            Self::Std(state) => KeyHasherState::Std(state.build_hasher()),
+            Self::Fx => KeyHasherState::Fx(FxHasher::default()),
+        }
+    }
+}
+
+pub(crate) enum KeyHasherState {
+    Std(DefaultHasher),
+    Fx(FxHasher),
+}
+
+impl Hasher for KeyHasherState {
+    fn finish(&self) -> u64 {
+        match self {
+            Self::Std(hasher) => hasher.finish(),
+            Self::Fx(hasher) => hasher.finish(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Std(hasher) => hasher.write(bytes),
+            Self::Fx(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        match self {
+            Self::Std(hasher) => hasher.write_u64(i),
+            Self::Fx(hasher) => hasher.write_u64(i),
+        }
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        match self {
+            Self::Std(hasher) => hasher.write_usize(i),
+            Self::Fx(hasher) => hasher.write_usize(i),
+        }
+    }
+}
+
+/// The word at a time hash of `rustc-hash`, see [`KeyHashing::Fx`]
+#[derive(Default)]
+pub(crate) struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(Self::SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+
+        for chunk in &mut chunks {
+            self.add(u64::from_le_bytes(
+                chunk.try_into().expect("chunks have 8 bytes"),
+            ));
+        }
+
+        for &byte in chunks.remainder() {
+            self.add(u64::from(byte));
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.add(u64::from(i));
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.add(u64::from(i));
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.add(u64::from(i));
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// A ring buffer of the most recently published values of an entry
+///
+/// Clones share the same buffer, so the entry can hand it to its publishing state while
+/// subscribers read it without locking the entry. The buffer is only locked to push or copy
+/// values, never while running user code.
+#[derive(Clone, Debug)]
+pub(crate) struct History<V>
+where
+    V: Clone,
+{
+    capacity: usize,
+    values: Arc<Mutex<VecDeque<V>>>,
+}
+
+impl<V> History<V>
+where
+    V: Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            values: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub fn push(&self, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+
+        if values.len() == self.capacity {
+            values.pop_front();
+        }
+
+        values.push_back(value);
+    }
+
+    /// The retained values, oldest first
+    pub fn to_vec(&self) -> Vec<V> {
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.iter().cloned().collect()
+    }
+}
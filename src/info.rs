@@ -0,0 +1,93 @@
+use std::time::Instant;
+
+/// Diagnostic information about a single entry, see
+/// [`SubscriptionMap::active_subscriptions`](crate::SubscriptionMap::active_subscriptions)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubscriptionInfo {
+    subscribers: usize,
+    pins: usize,
+    persistent: bool,
+    version: u64,
+    published_at: Instant,
+}
+
+impl SubscriptionInfo {
+    pub(crate) fn new(
+        subscribers: usize,
+        pins: usize,
+        persistent: bool,
+        version: u64,
+        published_at: Instant,
+    ) -> Self {
+        Self {
+            subscribers,
+            pins,
+            persistent,
+            version,
+            published_at,
+        }
+    }
+
+    /// The number of live subscription refs
+    pub fn subscribers(&self) -> usize {
+        self.subscribers
+    }
+
+    /// The number of pin guards keeping the entry alive
+    pub fn pins(&self) -> usize {
+        self.pins
+    }
+
+    /// Whether the entry is kept without subscribers, see
+    /// [`SubscriptionMap::release`](crate::SubscriptionMap::release)
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// The number of values published to subscribers, see
+    /// [`SubscriptionMap::current_version`](crate::SubscriptionMap::current_version)
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// When the entry was created or a value was published to its subscribers last
+    pub fn published_at(&self) -> Instant {
+        self.published_at
+    }
+}
+
+/// When an entry was created and how often it was published to, see
+/// [`SubscriptionMap::entry_meta`](crate::SubscriptionMap::entry_meta)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryMeta {
+    created_at: Instant,
+    last_published_at: Option<Instant>,
+    publish_count: u64,
+}
+
+impl EntryMeta {
+    pub(crate) fn new(created_at: Instant, published_at: Instant, publish_count: u64) -> Self {
+        Self {
+            created_at,
+            last_published_at: (publish_count > 0).then_some(published_at),
+            publish_count,
+        }
+    }
+
+    /// When the entry was inserted into the map
+    pub fn created_at(&self) -> Instant {
+        self.created_at
+    }
+
+    /// When a value was published to the subscribers of the entry last, `None` if the entry
+    /// still has its initial value
+    pub fn last_published_at(&self) -> Option<Instant> {
+        self.last_published_at
+    }
+
+    /// The number of values published to subscribers since the entry was created, which equals
+    /// its version
+    pub fn publish_count(&self) -> u64 {
+        self.publish_count
+    }
+}
@@ -0,0 +1,369 @@
+use crate::backend::{MapBackend, Storage};
+use crate::{SubscriptionEntry, SubscriptionMap};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::ops::{Bound, RangeBounds};
+use std::vec;
+
+/// An iterator over the keys of a subscription map.
+///
+/// For ordered maps the iterator yields keys in ascending order and is a cursor rather than a
+/// snapshot: the map, or each of its shards in turn, is locked only while looking up the next
+/// key, so entries inserted or removed during iteration may or may not be observed. Every key is
+/// yielded at most once though. Note that looking up the next key blocks the current thread while
+/// waiting for the lock.
+///
+/// The iterator is double ended, so the largest keys can be walked first via `rev`. Front and back
+/// meet in the middle without yielding a key twice.
+///
+/// Hashed maps can't be traversed with a cursor, so all keys are collected on the first call to
+/// `next`, locking one shard after another, and yielded in arbitrary order.
+///
+/// A cursor can't know how many keys are left without locking the map, so its `size_hint` is
+/// unbounded until the first key is looked up. Afterwards it is bounded by the number of entries
+/// at that point minus the keys yielded since, which keys inserted concurrently may exceed. A
+/// collected snapshot of a hashed map reports its exact length. Once the iterator returned `None` it stays exhausted, even if keys are
+/// inserted afterwards.
+#[derive(Debug)]
+pub struct Keys<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    cursor: Cursor<K, V, K>,
+}
+
+/// An iterator over the values of a subscription map, in the order of their keys. Like [`Keys`]
+/// it is a cursor rather than a snapshot. Values are those currently seen by subscribers.
+#[derive(Debug)]
+pub struct Values<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    cursor: Cursor<K, V, V>,
+}
+
+/// An iterator over the keys and values of a subscription map, see [`Keys`] and [`Values`]
+#[derive(Debug)]
+pub struct Iter<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    cursor: Cursor<K, V, (K, V)>,
+}
+
+/// Check if no key lies between the bounds, ranges of a `BTreeMap` panic in that case
+pub(crate) fn is_empty_range<K>(start: &Bound<K>, end: &Bound<K>) -> bool
+where
+    K: Ord,
+{
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start >= end,
+        _ => false,
+    }
+}
+
+/// Walks the entries of a map in ascending key order, turning every entry into an item
+#[derive(Debug)]
+struct Cursor<K, V, T>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    map: SubscriptionMap<K, V>,
+    /// The bounds of the keys not yielded yet, narrowed with every key yielded from either end
+    start: Bound<K>,
+    end: Bound<K>,
+    finished: bool,
+    /// The number of entries when the shards were first locked, minus the keys yielded since
+    remaining: Option<usize>,
+    snapshot: Option<vec::IntoIter<T>>,
+    project: fn(&K, &SubscriptionEntry<K, V>) -> T,
+}
+
+impl<K, V, T> Cursor<K, V, T>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn new<R>(
+        map: SubscriptionMap<K, V>,
+        range: R,
+        project: fn(&K, &SubscriptionEntry<K, V>) -> T,
+    ) -> Self
+    where
+        R: RangeBounds<K>,
+    {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+
+        Self {
+            map,
+            start,
+            end,
+            finished: false,
+            remaining: None,
+            snapshot: None,
+            project,
+        }
+    }
+
+    /// Check if no key is left between the bounds, see [`is_empty_range`]
+    fn is_exhausted(&self) -> bool {
+        is_empty_range(&self.start, &self.end)
+    }
+
+    /// Yield the item of the next entry from the front, or from the back if `back` is set
+    fn step(&mut self, back: bool) -> Option<T> {
+        if self.finished {
+            return None;
+        }
+
+        let item = self.advance(back);
+        self.finished = item.is_none();
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.finished {
+            return (0, Some(0));
+        }
+
+        // counting the remaining keys would lock the map, keys might be removed concurrently
+        match &self.snapshot {
+            Some(snapshot) => snapshot.size_hint(),
+            None => (0, self.remaining),
+        }
+    }
+
+    fn advance(&mut self, back: bool) -> Option<T> {
+        if let Some(snapshot) = &mut self.snapshot {
+            return match back {
+                true => snapshot.next_back(),
+                false => snapshot.next(),
+            };
+        }
+
+        if self.is_exhausted() {
+            return None;
+        }
+
+        let range = (self.start.clone(), self.end.clone());
+
+        let mut next: Option<(K, T)> = None;
+        let mut hashed = false;
+        let mut len = 0;
+
+        // shards are locked one after another, the closest next key of all shards wins
+        for shard in self.map.0.iter() {
+            let state = shard.read_blocking();
+
+            let entries = match &state.entries {
+                Storage::Ordered(entries) => entries,
+                Storage::Hashed(_) => {
+                    hashed = true;
+                    break;
+                }
+            };
+            len += entries.len();
+
+            let candidate = match back {
+                true => entries.range(range.clone()).next_back(),
+                false => entries.range(range.clone()).next(),
+            };
+
+            if let Some((key, entry)) = candidate {
+                let closer = next.as_ref().is_none_or(|(next, _)| match back {
+                    true => key > next,
+                    false => key < next,
+                });
+
+                if closer {
+                    next = Some((key.clone(), (self.project)(key, entry)));
+                }
+            }
+        }
+
+        if hashed {
+            self.take_snapshot();
+            return self.advance(back);
+        }
+
+        let remaining = self.remaining.get_or_insert(len);
+        *remaining = remaining.saturating_sub(1);
+
+        let (key, item) = next?;
+
+        match back {
+            true => self.end = Bound::Excluded(key),
+            false => self.start = Bound::Excluded(key),
+        }
+
+        Some(item)
+    }
+
+    /// Collect the items of all entries of a hashed map, which has no order to walk along
+    fn take_snapshot(&mut self) {
+        let mut items = Vec::new();
+
+        for shard in self.map.0.iter() {
+            let state = shard.read_blocking();
+            items.extend(
+                state
+                    .entries
+                    .iter()
+                    .filter(|(key, _)| (self.start.as_ref(), self.end.as_ref()).contains(*key))
+                    .map(|(key, entry)| (self.project)(key, entry)),
+            );
+        }
+
+        self.snapshot = Some(items.into_iter());
+    }
+}
+
+impl<K, V> Keys<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub(crate) fn new<R>(map: SubscriptionMap<K, V>, range: R) -> Self
+    where
+        R: RangeBounds<K>,
+    {
+        Self {
+            cursor: Cursor::new(map, range, |key, _| key.clone()),
+        }
+    }
+}
+
+impl<K, V> Values<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub(crate) fn new(map: SubscriptionMap<K, V>) -> Self {
+        Self {
+            cursor: Cursor::new(map, .., |_, entry| entry.observable.latest()),
+        }
+    }
+}
+
+impl<K, V> Iter<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub(crate) fn new(map: SubscriptionMap<K, V>) -> Self {
+        Self {
+            cursor: Cursor::new(map, .., |key, entry| {
+                (key.clone(), entry.observable.latest())
+            }),
+        }
+    }
+}
+
+impl<K, V> Iterator for Keys<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.step(false)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.cursor.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Keys<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.cursor.step(true)
+    }
+}
+
+impl<K, V> Iterator for Values<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.step(false)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.cursor.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Values<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.cursor.step(true)
+    }
+}
+
+impl<K, V> Iterator for Iter<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.step(false)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.cursor.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.cursor.step(true)
+    }
+}
+
+impl<K, V> FusedIterator for Keys<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+}
+
+impl<K, V> FusedIterator for Values<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+}
+
+impl<K, V> FusedIterator for Iter<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+}
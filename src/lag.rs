@@ -0,0 +1,157 @@
+use async_observable::Observable;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Distinguishes subscription refs across all maps, see [`SubscriberLag::subscriber`]
+static NEXT_SUBSCRIBER: AtomicU64 = AtomicU64::new(0);
+
+/// The position of a single subscription ref, updated whenever it receives a value
+#[derive(Debug)]
+pub(crate) struct Cursor {
+    subscriber: u64,
+    received: AtomicU64,
+}
+
+impl Cursor {
+    pub fn subscriber(&self) -> u64 {
+        self.subscriber
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::SeqCst)
+    }
+
+    /// Record the version of the received value, positions never move backwards
+    pub fn advance(&self, version: u64) {
+        self.received.fetch_max(version, Ordering::SeqCst);
+    }
+}
+
+/// The cursors of all subscription refs of an entry, shared by the entry and its refs
+///
+/// Refs only hold on to their own cursor, which is discarded from the list once they are dropped.
+/// Every advance is announced via the progress observable, see [`Cursors::observed`].
+#[derive(Clone)]
+pub(crate) struct Cursors {
+    cursors: Arc<Mutex<Vec<Weak<Cursor>>>>,
+    progress: Observable<()>,
+}
+
+impl Cursors {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Weak<Cursor>>> {
+        self.cursors.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Track a new subscription ref, which received everything up to the given version
+    pub fn register(&self, received: u64) -> Arc<Cursor> {
+        let cursor = Arc::new(Cursor {
+            subscriber: NEXT_SUBSCRIBER.fetch_add(1, Ordering::Relaxed),
+            received: AtomicU64::new(received),
+        });
+
+        let mut cursors = self.lock();
+        cursors.retain(|cursor| cursor.strong_count() > 0);
+        cursors.push(Arc::downgrade(&cursor));
+
+        cursor
+    }
+
+    /// The lag of every live subscription ref behind the given version, largest first
+    pub fn lags(&self, latest: u64) -> Vec<SubscriberLag> {
+        let mut cursors = self.lock();
+        cursors.retain(|cursor| cursor.strong_count() > 0);
+
+        let mut lags: Vec<_> = cursors
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|cursor| SubscriberLag::new(cursor.subscriber, cursor.received(), latest))
+            .collect();
+
+        lags.sort_by(|a, b| {
+            b.behind
+                .cmp(&a.behind)
+                .then(a.subscriber.cmp(&b.subscriber))
+        });
+        lags
+    }
+
+    /// Advance the cursor of a ref and wake up everyone waiting for refs to observe a version
+    pub fn advance(&mut self, cursor: &Cursor, version: u64) {
+        cursor.advance(version);
+        self.progress.modify(|_| {});
+    }
+
+    /// Wait until every live ref received the given version, or a newer one
+    pub async fn observed(&self, version: u64) {
+        let mut progress = self.progress.fork();
+
+        loop {
+            // synchronized before checking, so no advance in between gets lost
+            progress.synchronize();
+
+            let pending = self
+                .lock()
+                .iter()
+                .filter_map(Weak::upgrade)
+                .any(|cursor| cursor.received() < version);
+
+            if !pending {
+                return;
+            }
+
+            progress.next().await;
+        }
+    }
+}
+
+impl Default for Cursors {
+    fn default() -> Self {
+        Self {
+            cursors: Arc::default(),
+            progress: Observable::new(()),
+        }
+    }
+}
+
+impl fmt::Debug for Cursors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cursors = self.lock();
+        f.debug_tuple("Cursors").field(&cursors.len()).finish()
+    }
+}
+
+/// How far a single subscriber is behind the latest version of its entry, see
+/// [`SubscriptionMap::subscriber_lag`](crate::SubscriptionMap::subscriber_lag)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubscriberLag {
+    subscriber: u64,
+    received: u64,
+    behind: u64,
+}
+
+impl SubscriberLag {
+    pub(crate) fn new(subscriber: u64, received: u64, latest: u64) -> Self {
+        Self {
+            subscriber,
+            received,
+            behind: latest.saturating_sub(received),
+        }
+    }
+
+    /// Identifies the subscription ref, see
+    /// [`SubscriptionRef::subscriber_id`](crate::SubscriptionRef::subscriber_id)
+    pub fn subscriber(&self) -> u64 {
+        self.subscriber
+    }
+
+    /// The version of the value the subscriber received last
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The number of versions published since the subscriber received a value last
+    pub fn behind(&self) -> u64 {
+        self.behind
+    }
+}
@@ -1,292 +1,5650 @@
+//! A concurrent and self cleaning map of observable values.
+//!
+//! # Locking
+//!
+//! All entries live behind a single async read/write lock, or one per shard for maps created via
+//! [`SubscriptionMap::sharded`]. Every async method of [`SubscriptionMap`] awaits the lock instead
+//! of blocking, so waiting for a contended map never stalls the executor thread.
+//!
+//! Subscribing to present entries, e.g. via [`SubscriptionMap::get`], iterating keys and reading
+//! values only take a shared read lock and proceed concurrently. Inserting entries and releasing
+//! subscriptions take the lock exclusively.
+//!
+//! Publishing only looks up the entry under the read lock and then locks the entry itself, so
+//! publishes to different keys never wait for each other. Closures passed to the map, e.g. to
+//! [`SubscriptionMap::modify_and_publish`], run while the entry is locked and should be kept
+//! short. Methods touching multiple entries at once, like [`SubscriptionMap::publish_batch`],
+//! lock them in ascending key order.
+//!
+//! The only places locking the map synchronously are those which can't be async: dropping a
+//! [`SubscriptionRef`] or [`PinGuard`], [`SubscriptionRef::unsubscribe`] and advancing a [`Keys`]
+//! iterator. Use
+//! [`SubscriptionRef::unsubscribe_async`] to release a subscription without blocking.
+
 use anyhow::Context;
+use async_lock::{Mutex, MutexGuard, MutexGuardArc};
 use async_observable::Observable;
-use async_std::sync::Mutex;
-use async_std::task::block_on;
-use std::collections::BTreeMap;
+use futures::{future, stream, Stream, StreamExt};
+use std::any::Any;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::convert::Infallible;
 use std::fmt::Debug;
+use std::future::Future;
 use std::hash::Hash;
-use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::iter::FromIterator;
+use std::ops::RangeBounds;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+mod backend;
+mod bounded;
+mod builder;
+mod callback;
+mod changes;
+mod clear;
+mod debounce;
+mod delta;
+mod detect;
+mod diff;
+mod entry;
+mod error;
+mod events;
+mod group;
+mod hasher;
+mod history;
+mod info;
+mod keys;
+mod lag;
+mod mapped;
+mod multi;
+mod outcome;
+mod pin;
+mod producer;
+mod ratelimit;
+mod replay;
+mod runtime;
+mod sequence;
+mod shards;
+mod shared;
+mod sink;
+mod transaction;
+mod weak;
+
+use backend::{MapBackend, Storage};
+use bounded::BoundedSinks;
+use debounce::Debounce;
+use delta::DeltaSinks;
+use detect::Detector;
+use events::Events;
+use hasher::KeyHasher;
+use history::History;
+use lag::{Cursor, Cursors};
+use ratelimit::RateLimiter;
+use sequence::Sequence;
+use shards::{Shards, Slot};
+
+pub use bounded::{Backpressure, BoundedSubscription};
+pub use builder::{EvictionPolicy, SubscriptionMapBuilder};
+pub use callback::CallbackHandle;
+pub use clear::ClearMode;
+pub use delta::{ApplyDelta, DeltaSubscription, Update};
+pub use detect::ChangeDetect;
+pub use diff::MapDiff;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{
+    BufferFullError, CapacityError, CasError, ClosedError, Elapsed, InsertError, PublishError,
+    RateLimitedError, TimeoutError,
+};
+pub use events::{KeyEvent, MapEvent};
+pub use group::GroupSubscription;
+pub use hasher::KeyHashing;
+pub use info::{EntryMeta, SubscriptionInfo};
+pub use keys::{Iter, Keys, Values};
+pub use lag::SubscriberLag;
+pub use mapped::MappedSubscription;
+pub use multi::MultiSubscription;
+pub use outcome::PublishOutcome;
+pub use pin::PinGuard;
+pub use replay::Replay;
+pub use sequence::Sequenced;
+pub use shared::SharedSubscriptionMap;
+pub use sink::PublishSink;
+pub use transaction::Transaction;
+pub use weak::WeakSubscriptionRef;
 
 /// A concurrent and self cleaning map of observable values
 #[derive(Clone, Debug)]
-pub struct SubscriptionMap<K, V>(Arc<Mutex<BTreeMap<K, SubscriptionEntry<V>>>>)
+pub struct SubscriptionMap<K, V>(Arc<Shards<K, V>>)
 where
     K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug;
+    V: Clone;
 
-/// A single observable entry and its subscription count
-#[derive(Clone, Debug)]
-struct SubscriptionEntry<V>
+/// The lock protected state of a subscription map
+#[derive(Debug)]
+struct MapState<K, V>
 where
-    V: Clone + Debug,
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
 {
-    observable: Observable<V>,
-    rc: usize,
+    entries: Storage<K, SubscriptionEntry<K, V>>,
+    /// Identifier handed to the next created entry, used to tell apart entries of the same key
+    next_id: u64,
+    /// Bumped whenever an entry is inserted or removed, used to wake up waiting tasks
+    membership: Observable<u64>,
+    /// Keys whose initial value is currently computed asynchronously
+    initializing: BTreeSet<K>,
+    /// How long unused entries are kept before they are removed, see
+    /// [`SubscriptionMap::with_linger`]
+    linger: Option<Duration>,
+    /// How long entries are kept without being published to, see [`SubscriptionMapBuilder::ttl`]
+    ttl: Option<Duration>,
+    /// The maximum number of entries, see [`SubscriptionMapBuilder::capacity`]
+    capacity: Option<(usize, EvictionPolicy)>,
+    /// Listeners of lifecycle events, shared by all shards of the map
+    events: Events<K>,
+    /// Passed to every new entry, see [`SubscriptionMapBuilder::change_detect`]
+    detect: Detector<V>,
+    /// The number of values every new entry retains, see [`SubscriptionMapBuilder::history`]
+    history: Option<usize>,
+    /// Cloned into every new entry, see [`SubscriptionMapBuilder::coalesce`] and
+    /// [`SubscriptionMapBuilder::debounce`]
+    debounce: Option<Debounce<V>>,
+    /// Cloned into every new entry, see [`SubscriptionMapBuilder::rate_limit`]
+    rate_limit: Option<RateLimiter>,
+    /// Set by [`SubscriptionMap::close`], no entries are inserted anymore afterwards
+    closed: bool,
 }
 
-impl<V> SubscriptionEntry<V>
+impl<K, V> MapState<K, V>
 where
-    V: Clone + Debug,
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
 {
-    pub fn new(value: V) -> Self {
+    fn new(entries: Storage<K, SubscriptionEntry<K, V>>) -> Self {
         Self {
-            observable: Observable::new(value),
-            rc: 0,
+            entries,
+            next_id: 0,
+            membership: Observable::new(0),
+            initializing: BTreeSet::new(),
+            linger: None,
+            ttl: None,
+            capacity: None,
+            events: Events::default(),
+            detect: Detector::default(),
+            history: None,
+            debounce: None,
+            rate_limit: None,
+            closed: false,
         }
     }
-}
 
-impl<K, V> SubscriptionMap<K, V>
-where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug,
-{
-    pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(BTreeMap::new())))
+    fn notify_membership(&mut self) {
+        self.membership.modify(|generation| *generation += 1);
     }
 
-    pub async fn get_or_insert(&self, key: K, value: V) -> SubscriptionRef<K, V> {
-        let mut map = self.0.lock().await;
-        let entry = {
-            let entry = SubscriptionEntry::new(value);
-            map.entry(key.clone()).or_insert(entry)
+    fn try_get_or_insert_with<F>(
+        &mut self,
+        key: K,
+        value: F,
+    ) -> Result<&mut SubscriptionEntry<K, V>, InsertError>
+    where
+        F: FnOnce() -> V,
+    {
+        if self.closed {
+            return Err(ClosedError.into());
+        }
+
+        self.make_room(&key)?;
+
+        if !self.entries.contains_key(&key) {
+            let id = self.next_id();
+            let entry = SubscriptionEntry::new(id, key.clone(), value(), self.detect.clone())
+                .with_history(self.history)
+                .with_debounce(self.debounce.clone())
+                .with_rate_limit(self.rate_limit.clone());
+            self.entries.insert(key.clone(), entry);
+            self.notify_membership();
+            self.events.emit(MapEvent::Inserted(key.clone()));
+        }
+
+        Ok(self.entries.get_mut(&key).expect("entry was just inserted"))
+    }
+
+    /// Ensure the key can be inserted without exceeding the capacity, evicting another entry if
+    /// the policy allows to
+    fn make_room(&mut self, key: &K) -> Result<(), CapacityError> {
+        let (capacity, policy) = match self.capacity {
+            Some(capacity) => capacity,
+            None => return Ok(()),
         };
 
-        SubscriptionRef::new(key, self.clone(), entry).unwrap()
+        if self.entries.contains_key(key) || self.entries.len() < capacity {
+            return Ok(());
+        }
+
+        if policy == EvictionPolicy::Reject {
+            return Err(CapacityError::new(capacity));
+        }
+
+        let victim = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.pins == 0 && !entry.persistent)
+            .filter_map(|(key, entry)| {
+                // a locked entry is being published to right now, so it isn't a candidate
+                let published_at = entry.state.try_lock()?.published_at;
+                Some((published_at, key.clone()))
+            })
+            .min_by_key(|(published_at, _)| *published_at)
+            .map(|(_, key)| key)
+            .ok_or_else(|| CapacityError::new(capacity))?;
+
+        log::trace!("evicting least recently published key {:?}", victim);
+        self.evict(&victim);
+
+        Ok(())
     }
 
-    #[cfg(test)]
-    async fn snapshot(&self) -> BTreeMap<K, SubscriptionEntry<V>> {
-        self.0.lock().await.deref().clone()
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
     }
 
-    async fn remove(&self, key: &K) -> anyhow::Result<()> {
-        let mut map = self.0.lock().await;
+    /// Remove the entry regardless of outstanding subscriptions and notify its subscribers
+    fn evict(&mut self, key: &K) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                entry.evict();
+                self.notify_membership();
+                self.events.emit(MapEvent::Removed(key.clone()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove an entry which just became unused, or only mark it as idle if the map lingers
+    fn remove_unused(&mut self, key: &K) -> anyhow::Result<()> {
+        if self.linger.is_none() {
+            return self.remove(key);
+        }
+
+        let entry = self
+            .entries
+            .get_mut(key)
+            .with_context(|| format!("unable to mark not present key {:?} idle", key))?;
+
+        entry.idle_since = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Remove all entries which have been idle for the linger duration and return the time until
+    /// the next idle entry is due, or `None` if the map doesn't linger.
+    fn sweep(&mut self) -> Option<Duration> {
+        let linger = self.linger?;
+        let mut next = linger;
+
+        let expired: Vec<K> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.is_unused())
+            .filter_map(|(key, entry)| {
+                let idle = entry.idle_since?.elapsed();
 
-        let entry = map
+                match linger.checked_sub(idle) {
+                    Some(remaining) if !remaining.is_zero() => {
+                        next = next.min(remaining);
+                        None
+                    }
+                    _ => Some(key.clone()),
+                }
+            })
+            .collect();
+
+        for key in expired {
+            log::trace!("removing idle key {:?}", key);
+
+            if let Err(e) = self.remove(&key) {
+                log::error!("error occurred while sweeping idle key {:?}: {}", key, e);
+            }
+        }
+
+        Some(next)
+    }
+
+    /// Evict all entries which haven't been published to within the ttl and return the time
+    /// until the next entry is due, or `None` if entries don't expire.
+    fn expire(&mut self) -> Option<Duration> {
+        let ttl = self.ttl?;
+        let mut next = ttl;
+
+        let expired: Vec<K> = self
+            .entries
+            .iter()
+            .filter_map(|(key, entry)| {
+                // a locked entry is being published to right now, so it isn't stale
+                let published = entry.state.try_lock()?.published_at.elapsed();
+
+                match ttl.checked_sub(published) {
+                    Some(remaining) if !remaining.is_zero() => {
+                        next = next.min(remaining);
+                        None
+                    }
+                    _ => Some(key.clone()),
+                }
+            })
+            .collect();
+
+        for key in expired {
+            log::trace!("evicting stale key {:?}", key);
+            self.evict(&key);
+        }
+
+        Some(next)
+    }
+
+    fn remove(&mut self, key: &K) -> anyhow::Result<()> {
+        let entry = self
+            .entries
             .get(key)
-            .with_context(|| format!("unable remove not present key {:?} in {:#?}", key, self))?;
+            .with_context(|| format!("unable remove not present key {:?}", key))?;
 
         assert!(
-            entry.rc == 0,
+            entry.rc.is_zero(),
             "invalid removal of referenced subscription at {:?}",
             key
         );
 
-        map.remove(key);
+        self.entries.remove(key);
+        self.notify_membership();
+        self.events.emit(MapEvent::Removed(key.clone()));
 
         Ok(())
     }
 }
 
-impl<K, V> SubscriptionMap<K, V>
+/// A single observable entry and its subscription count
+#[derive(Clone, Debug)]
+struct SubscriptionEntry<K, V>
 where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug + Eq,
+    V: Clone,
 {
-    /// Check if the provided value differs from the observable and return the info if a publish
-    /// was made.
-    pub async fn publish_if_changed(&self, key: &K, value: V) -> anyhow::Result<bool> {
-        let mut map = self.0.lock().await;
-        let entry = map
-            .get_mut(key)
-            .with_context(|| format!("unable publish new version of not present key {:?}", key))?;
+    id: u64,
+    /// Shared with all handles to the entry, updated when the entry is renamed
+    slot: Arc<Slot<K>>,
+    /// Handle to fork subscriptions from, publishing goes through the entry state
+    observable: Observable<V>,
+    /// Shared with all subscription refs, so they can be cloned without locking the map
+    rc: Arc<RefCount>,
+    /// Persistent entries are kept in the map even if no one subscribes to them
+    persistent: bool,
+    /// The number of pin guards keeping the entry alive, see [`SubscriptionMap::pin`]
+    pins: usize,
+    /// When the entry became unused last, only tracked if the map lingers
+    idle_since: Option<Instant>,
+    /// Shared with the entry state, which pushes every emitted value
+    history: Option<History<V>>,
+    /// Shared with all subscription refs, set once the entry is forcefully removed from the map
+    evicted: Arc<AtomicBool>,
+    /// Shared with the entry state and all subscription refs
+    version: Sequence,
+    /// The positions of all subscription refs, see [`SubscriptionMap::subscriber_lag`]
+    cursors: Cursors,
+    /// Locked separately from the map, so publishes to different keys don't wait for each other
+    state: Arc<Mutex<EntryState<V>>>,
+}
+
+impl<K, V> SubscriptionEntry<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    pub fn new(id: u64, key: K, value: V, detect: Detector<V>) -> Self {
+        let observable = Observable::new(value);
+        let evicted = Arc::new(AtomicBool::new(false));
+        let rc = Arc::new(RefCount::default());
+        let version = Sequence::default();
 
-        Ok(entry.observable.publish_if_changed(value))
+        Self {
+            id,
+            slot: Arc::new(Slot::new(key)),
+            observable: observable.clone(),
+            rc: rc.clone(),
+            persistent: false,
+            pins: 0,
+            idle_since: None,
+            history: None,
+            evicted: evicted.clone(),
+            version: version.clone(),
+            cursors: Cursors::default(),
+            state: Arc::new(Mutex::new(EntryState {
+                observable,
+                debounce: None,
+                history: None,
+                rate_limit: None,
+                deltas: DeltaSinks::default(),
+                bounded: BoundedSinks::default(),
+                version: version.clone(),
+                created_at: Instant::now(),
+                published_at: Instant::now(),
+                evicted,
+                rc,
+                detect,
+            })),
+        }
     }
 
-    pub async fn modify_and_publish<F, R>(&self, key: &K, modify: F) -> anyhow::Result<()>
-    where
-        F: FnOnce(&mut V) -> R,
-    {
-        let mut map = self.0.lock().await;
-        let entry = map
-            .get_mut(key)
-            .with_context(|| format!("unable modify not present key {:?}", key))?;
+    pub fn persistent(id: u64, key: K, value: V, detect: Detector<V>) -> Self {
+        Self {
+            persistent: true,
+            ..Self::new(id, key, value, detect)
+        }
+    }
 
-        entry.observable.modify(|v| {
-            modify(v);
-        });
+    /// Check if the entry can be removed from the map
+    pub fn is_unused(&self) -> bool {
+        self.rc.is_zero() && self.pins == 0 && !self.persistent
+    }
 
-        Ok(())
+    /// Mark the entry as evicted and wake up all subscribers by publishing the current value once
+    /// more, so they get the chance to notice.
+    pub fn evict(mut self) {
+        self.evicted.store(true, Ordering::SeqCst);
+        self.observable.modify(|_| {});
     }
-}
 
-impl<K, V> Default for SubscriptionMap<K, V>
-where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug,
-{
-    fn default() -> Self {
-        Self::new()
+    /// Retain the most recently published values of a new entry, like
+    /// [`SubscriptionEntry::retain_history`]
+    pub fn with_history(mut self, capacity: Option<usize>) -> Self {
+        if let Some(capacity) = capacity {
+            let history = History::new(capacity);
+            history.push(self.observable.latest());
+
+            let state = Arc::get_mut(&mut self.state).expect("a new entry isn't shared yet");
+            state.get_mut().history = Some(history.clone());
+            self.history = Some(history);
+        }
+
+        self
+    }
+
+    /// Debounce publishes to a new entry, whose pending values are flushed by the map, see
+    /// [`SubscriptionMapBuilder::coalesce`]
+    pub fn with_debounce(mut self, debounce: Option<Debounce<V>>) -> Self {
+        if debounce.is_some() {
+            let state = Arc::get_mut(&mut self.state).expect("a new entry isn't shared yet");
+            state.get_mut().debounce = debounce;
+        }
+
+        self
+    }
+
+    /// Limit the publishes to a new entry, see [`SubscriptionMapBuilder::rate_limit`]
+    pub fn with_rate_limit(mut self, rate_limit: Option<RateLimiter>) -> Self {
+        if rate_limit.is_some() {
+            let state = Arc::get_mut(&mut self.state).expect("a new entry isn't shared yet");
+            state.get_mut().rate_limit = rate_limit;
+        }
+
+        self
+    }
+
+    /// Start retaining the most recently published values, beginning with the current one.
+    ///
+    /// Returns the history along with the version of its first value, to be handed to
+    /// [`EntryState::retain_history`] once the map is unlocked.
+    pub fn retain_history(&mut self, capacity: usize) -> (History<V>, u64) {
+        let history = History::new(capacity);
+        history.push(self.observable.latest());
+
+        self.history = Some(history.clone());
+        (history, self.version.get())
     }
 }
 
-/// A transparent wrapper for the underlying subscription in the map
-/// which manages the subscription count and removes the observable if no one
-/// holds a subscription to it.
+/// The publishing side of an entry, everything modified when emitting a value
 #[derive(Debug)]
-#[must_use = "entries are removed as soon as no one subscribes to them"]
-pub struct SubscriptionRef<K, V>
+struct EntryState<V>
 where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug,
+    V: Clone,
 {
-    key: K,
-    owner: SubscriptionMap<K, V>,
     observable: Observable<V>,
+    debounce: Option<Debounce<V>>,
+    history: Option<History<V>>,
+    rate_limit: Option<RateLimiter>,
+    deltas: DeltaSinks<V>,
+    bounded: BoundedSinks<V>,
+    /// Incremented with every value emitted to subscribers
+    version: Sequence,
+    /// When the entry was inserted into the map
+    created_at: Instant,
+    /// When the entry was created or a value was emitted last
+    published_at: Instant,
+    evicted: Arc<AtomicBool>,
+    rc: Arc<RefCount>,
+    detect: Detector<V>,
 }
 
-impl<K, V> SubscriptionRef<K, V>
+impl<V> EntryState<V>
 where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug,
+    V: Clone,
 {
-    fn new(
-        key: K,
-        owner: SubscriptionMap<K, V>,
-        entry: &mut SubscriptionEntry<V>,
-    ) -> anyhow::Result<Self> {
-        entry.rc += 1;
+    /// The number of live subscription refs of the entry
+    pub fn subscribers(&self) -> usize {
+        self.rc.get()
+    }
 
-        Ok(Self {
-            key,
-            owner,
-            observable: entry.observable.clone(),
-        })
+    /// Retain the values published from now on in the history started by
+    /// [`SubscriptionEntry::retain_history`], catching up with the latest value if one was
+    /// published since the history was started
+    pub fn retain_history(&mut self, (history, version): (History<V>, u64)) {
+        if self.history.is_some() {
+            return;
+        }
+
+        if self.version.get() != version {
+            history.push(self.observable.latest());
+        }
+
+        self.history = Some(history);
     }
-}
 
-impl<K, V> Deref for SubscriptionRef<K, V>
-where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug,
-{
-    type Target = Observable<V>;
+    /// Lock the state once the buffers of all waiting bounded subscribers have room for another
+    /// value, see [`SubscriptionMap::subscribe_bounded`].
+    ///
+    /// The state is unlocked while waiting, so readers of the entry aren't held up. A full buffer
+    /// of a rejecting subscriber is returned along with the locked state instead, as publishing
+    /// an unchanged value doesn't need room.
+    pub async fn lock_with_room(
+        state: &Mutex<Self>,
+    ) -> (MutexGuard<'_, Self>, Result<(), BufferFullError>) {
+        loop {
+            let mut guard = state.lock().await;
 
-    fn deref(&self) -> &Self::Target {
-        &self.observable
+            match guard.bounded.reserve() {
+                Ok(Some(full)) => {
+                    drop(guard);
+                    full.wait().await;
+                }
+                Ok(None) => return (guard, Ok(())),
+                Err(e) => return (guard, Err(e)),
+            }
+        }
     }
-}
 
-impl<K, V> DerefMut for SubscriptionRef<K, V>
-where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug,
-{
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.observable
+    /// Lock the state once the entry accepts a publish, see [`EntryState::lock_with_room`] and
+    /// [`SubscriptionMapBuilder::rate_limit`]
+    pub async fn lock_admitted(state: &Mutex<Self>) -> anyhow::Result<MutexGuard<'_, Self>> {
+        let (mut state, room) = Self::lock_with_room(state).await;
+        room?;
+        state.take_token()?;
+
+        Ok(state)
     }
-}
 
-impl<K, V> Drop for SubscriptionRef<K, V>
-where
-    K: Clone + Debug + Eq + Hash + Ord,
-    V: Clone + Debug,
-{
-    fn drop(&mut self) {
-        log::trace!("drop for subscription ref for key {:?}", self.key);
+    /// Take a token of the rate limit of the entry for a publish, if it is limited
+    fn take_token(&mut self) -> Result<(), RateLimitedError> {
+        match &mut self.rate_limit {
+            Some(rate_limit) => rate_limit.acquire().map_err(RateLimitedError::new),
+            None => Ok(()),
+        }
+    }
 
-        let mut map = block_on(self.owner.0.lock());
-        let mut entry = match map.get_mut(&self.key) {
-            Some(entry) => entry,
-            None => {
-                log::error!("could not obtain rc in subscription map {:#?}", map.deref());
-                return;
-            }
+    /// Check whether a token is left without taking it, see [`EntryState::check_admission`]
+    fn check_token(&mut self) -> Result<(), RateLimitedError> {
+        match &mut self.rate_limit {
+            Some(rate_limit) => rate_limit.check().map_err(RateLimitedError::new),
+            None => Ok(()),
+        }
+    }
+
+    /// Check for room without unlocking the entry to wait for it, for publishers holding several
+    /// entries or the map locked. Full buffers of waiting subscribers fail as well then.
+    fn try_room(&mut self) -> Result<(), BufferFullError> {
+        match self.bounded.reserve()? {
+            Some(full) => Err(full.error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Check whether the entry accepts a publish right away without taking a token, to admit
+    /// publishes to several entries all or nothing
+    fn check_admission(&mut self) -> Result<(), PublishError> {
+        self.check_token()?;
+        self.try_room()?;
+
+        Ok(())
+    }
+
+    /// Publish the value if the entry accepts it right away, see [`EntryState::try_room`]
+    fn try_publish(&mut self, value: V) -> Result<(), PublishError> {
+        self.take_token()?;
+        self.try_room()?;
+        self.publish(value);
+
+        Ok(())
+    }
+
+    /// The most recent value of the entry, including not yet emitted debounced values
+    pub fn latest(&self) -> V {
+        match self.debounce.as_ref().and_then(Debounce::pending) {
+            Some(pending) => pending.clone(),
+            None => self.observable.latest(),
+        }
+    }
+
+    /// Publish the value, subject to debouncing if configured
+    pub fn publish(&mut self, value: V) {
+        let value = match &mut self.debounce {
+            Some(debounce) => match debounce.admit(value) {
+                Some(value) => value,
+                None => return,
+            },
+            None => value,
         };
 
-        entry.rc -= 1;
+        self.emit(value);
+    }
 
-        if entry.rc == 0 {
-            drop(map);
-            let res = block_on(self.owner.remove(&self.key));
+    /// Apply the delta to the latest value and publish the result, subject to debouncing
+    pub fn publish_delta<D>(&mut self, delta: &D)
+    where
+        V: ApplyDelta<D>,
+        D: Any,
+    {
+        let mut value = self.latest();
+        value.apply_delta(delta);
 
-            if let Err(e) = res {
-                log::error!("error occurred while cleanup subscription ref {}", e);
-            }
+        match self.debounce {
+            Some(_) => self.publish(value),
+            None => self.emit_with(value, Some(delta)),
         }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::SubscriptionMap;
+    /// Hand the value to subscribers, every publish ends up here eventually
+    fn emit(&mut self, value: V) {
+        self.emit_with(value, None)
+    }
 
-    macro_rules! assert_map_len {
-        ($map:ident, $len:expr) => {
-            assert_eq!($map.snapshot().await.len(), $len);
-        };
+    /// Hand the value to subscribers, delta subscribers receive the delta it resulted from if any
+    fn emit_with(&mut self, value: V, delta: Option<&dyn Any>) {
+        // the entry might have been evicted while the publisher waited for this state
+        if self.evicted.load(Ordering::SeqCst) {
+            log::trace!("dropped value published to evicted entry");
+            return;
+        }
+
+        if let Some(history) = &self.history {
+            history.push(value.clone());
+        }
+
+        self.deltas.send(&value, delta);
+        self.bounded.send(&value);
+        self.published_at = Instant::now();
+
+        // subscribers read the version under the same lock, see SubscriptionRef::next_sequenced
+        let mut version = self.version.lock();
+        *version += 1;
+        self.observable.publish(value);
     }
 
-    macro_rules! assert_ref_count {
-        ($map:ident, $key:expr, $rc:expr) => {
-            assert_eq!($map.snapshot().await.get($key).unwrap().rc, $rc);
-        };
+    /// Apply a fallible modification to a copy of the latest value and only publish it if the
+    /// modification succeeded, leaving the entry untouched otherwise.
+    pub fn try_modify<F, R, E>(&mut self, modify: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut V) -> Result<R, E>,
+    {
+        let mut value = self.latest();
+        let result = modify(&mut value)?;
+        self.publish(value);
+
+        Ok(result)
     }
 
-    #[async_std::test]
-    async fn should_immediately_remove_unused() {
-        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
-        assert_map_len!(map, 0);
+    /// Publish the value if `changed` considers it different from the one currently seen by
+    /// subscribers. The closure is called with the current and the new value. Changes fail if
+    /// there's no room for them, see [`EntryState::lock_with_room`].
+    pub fn publish_if_changed_with<F>(
+        &mut self,
+        value: V,
+        changed: F,
+        room: Result<(), BufferFullError>,
+    ) -> Result<bool, BufferFullError>
+    where
+        F: FnOnce(&V, &V) -> bool,
+    {
+        let changed = changed(&self.observable.latest(), &value);
+        self.publish_changed(value, changed, room)
+    }
 
-        let _ = map.get_or_insert(1, 1).await;
-        assert_map_len!(map, 0);
+    /// Publish the value like [`EntryState::publish_or_discard`], unless it is a change there's
+    /// no room for. Unchanged values never need room, as they aren't queued.
+    fn publish_changed(
+        &mut self,
+        value: V,
+        changed: bool,
+        room: Result<(), BufferFullError>,
+    ) -> Result<bool, BufferFullError> {
+        if changed {
+            room?;
+        }
 
-        let _ = map.get_or_insert(2, 2).await;
-        assert_map_len!(map, 0);
+        Ok(self.publish_or_discard(value, changed))
     }
 
-    #[async_std::test]
-    async fn should_remove_entries_on_ref_drop() {
-        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
-        assert_map_len!(map, 0);
+    /// Publish the value if it is a change, otherwise drop it along with a pending debounced
+    /// value, which is superseded by the unchanged one
+    fn publish_or_discard(&mut self, value: V, changed: bool) -> bool {
+        if changed {
+            self.publish(value);
+        } else if let Some(debounce) = &mut self.debounce {
+            debounce.discard();
+        }
 
-        let ref_one = map.get_or_insert(1, 1).await;
-        assert_map_len!(map, 1);
+        changed
+    }
+}
 
-        let ref_two = map.get_or_insert(2, 2).await;
-        assert_map_len!(map, 2);
+impl<V> EntryState<V>
+where
+    V: Clone + PartialEq,
+{
+    /// Whether the value differs from the one currently seen by subscribers, according to the
+    /// change detection of the map
+    fn is_changed(&self, value: &V) -> bool {
+        self.detect.is_changed(&self.observable.latest(), value)
+    }
 
-        drop(ref_one);
-        assert_map_len!(map, 1);
-        assert!(map.snapshot().await.get(&1).is_none());
-        assert!(map.snapshot().await.get(&2).is_some());
+    /// Publish the value if it differs from the one currently seen by subscribers, according to
+    /// the change detection of the map. Changes fail if there's no room for them, see
+    /// [`EntryState::lock_with_room`].
+    pub fn publish_if_changed_with_room(
+        &mut self,
+        value: V,
+        room: Result<(), BufferFullError>,
+    ) -> Result<bool, BufferFullError> {
+        let changed = self.is_changed(&value);
+        self.publish_changed(value, changed, room)
+    }
 
-        drop(ref_two);
-        assert_map_len!(map, 0);
-        assert!(map.snapshot().await.get(&1).is_none());
-        assert!(map.snapshot().await.get(&2).is_none());
+    /// Take a token and publish the value if it is a change there's room for, see
+    /// [`EntryState::lock_with_room`]
+    fn publish_if_admitted(
+        &mut self,
+        value: V,
+        room: Result<(), BufferFullError>,
+    ) -> Result<bool, PublishError> {
+        self.take_token()?;
+
+        Ok(self.publish_if_changed_with_room(value, room)?)
     }
 
-    #[async_std::test]
-    async fn should_keep_track_of_ref_count() {
-        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
-        assert_map_len!(map, 0);
+    /// Modify a copy of the latest value and only publish it if the modification changed it and
+    /// there's room for it, see [`EntryState::lock_with_room`]
+    pub fn modify_if_changed<F, R>(
+        &mut self,
+        modify: F,
+        room: Result<(), BufferFullError>,
+    ) -> Result<(R, bool), BufferFullError>
+    where
+        F: FnOnce(&mut V) -> R,
+    {
+        let mut value = self.latest();
+        let result = modify(&mut value);
+        let changed = self.publish_if_changed_with_room(value, room)?;
 
-        let ref_one = map.get_or_insert(1, 1).await;
-        assert_ref_count!(map, &1, 1);
+        Ok((result, changed))
+    }
 
-        let ref_two = map.get_or_insert(1, 1).await;
-        assert_ref_count!(map, &1, 2);
+    /// Emit a pending debounced value if due and return the time until the next flush, or `None`
+    /// if the entry isn't debounced. A value without room stays pending for the next flush.
+    pub fn flush_debounced(&mut self) -> Option<Duration> {
+        let (value, wait) = self.debounce.as_mut()?.flush();
 
-        drop(ref_one);
-        assert_ref_count!(map, &1, 1);
+        if let Some(value) = value {
+            if self.is_changed(&value) {
+                match self.try_room() {
+                    Ok(()) => self.emit(value),
+                    Err(_) => self.debounce.as_mut()?.defer(value),
+                }
+            }
+        }
 
-        drop(ref_two);
-        assert_map_len!(map, 0);
+        Some(wait)
+    }
+}
+
+/// The number of subscription refs held for a single entry
+///
+/// All changes are checked instead of silently wrapping around. The count is atomic, so
+/// subscribing to a present entry only requires a shared read lock of the map. Decrementing
+/// happens under the write lock, as it might remove the entry.
+#[derive(Debug, Default)]
+struct RefCount(AtomicUsize);
+
+impl RefCount {
+    #[cfg(test)]
+    fn new(count: usize) -> Self {
+        Self(AtomicUsize::new(count))
     }
 
-    #[async_std::test]
-    #[should_panic]
-    async fn shouldnt_remove_if_rc_is_not_zero() {
-        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
-        assert_map_len!(map, 0);
+    fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
 
-        let _ref = map.get_or_insert(1, 1).await;
-        assert_ref_count!(map, &1, 1);
+    fn is_zero(&self) -> bool {
+        self.get() == 0
+    }
 
-        map.remove(&1).await.unwrap();
+    /// Increment the count and return the previous one
+    fn increment(&self) -> anyhow::Result<usize> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |rc| rc.checked_add(1))
+            .ok()
+            .context("subscription count overflow")
+    }
+
+    /// Decrement the count and return the previous one
+    fn decrement(&self) -> anyhow::Result<usize> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |rc| rc.checked_sub(1))
+            .ok()
+            .context("subscription count underflow")
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// Create an empty map with ordered keys, see [`SubscriptionMap::ordered`]
+    pub fn new() -> Self {
+        Self::ordered()
+    }
+
+    /// Create an empty map backed by a `BTreeMap`, which keeps its keys in ascending order
+    pub fn ordered() -> Self {
+        Self::with_storage(Storage::Ordered(BTreeMap::new()))
+    }
+
+    /// Create an empty map backed by a `HashMap`, which offers faster lookups for large numbers of
+    /// keys. Keys aren't ordered though, so [`SubscriptionMap::keys`] yields them in arbitrary
+    /// order. A faster hasher can be configured via [`SubscriptionMapBuilder::hasher`].
+    pub fn hashed() -> Self {
+        Self::with_storage(Storage::Hashed(HashMap::default()))
+    }
+
+    /// Create an empty map split into the given number of shards, each with its own lock and
+    /// ordered keys.
+    ///
+    /// Operations on keys of different shards don't contend for the same lock, which helps with
+    /// large numbers of keys and concurrent publishers. Operations spanning the whole map, like
+    /// [`SubscriptionMap::publish_many`], lock all shards and get more expensive in return.
+    pub fn sharded(shards: usize) -> Self {
+        let shards = (0..shards)
+            .map(|_| MapState::new(Storage::Ordered(BTreeMap::new())))
+            .collect();
+
+        Self(Arc::new(Shards::new(shards, KeyHasher::default())))
+    }
+
+    /// Create an empty map with ordered keys which keeps unused entries for the given duration
+    /// before removing them.
+    ///
+    /// An entry whose last subscription is dropped stays in the map and is reused by subscribers
+    /// showing up within the linger duration, which avoids recreating entries during rapid
+    /// subscribe and unsubscribe cycles. Idle entries are removed by a background task after at
+    /// least the linger duration, which stops as soon as the map is dropped.
+    pub fn with_linger(linger: Duration) -> Self
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        Self::builder().linger(linger).build()
+    }
+
+    /// Configure a map beyond the defaults of the other constructors, e.g. to let entries expire
+    pub fn builder() -> SubscriptionMapBuilder<K, V> {
+        SubscriptionMapBuilder::new()
+    }
+
+    fn with_storage(storage: Storage<K, SubscriptionEntry<K, V>>) -> Self {
+        Self(Arc::new(Shards::single(MapState::new(storage))))
+    }
+
+    /// Subscribe to the key, inserting it with the given value if missing.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, see
+    /// [`SubscriptionMapBuilder::capacity`]. Use [`SubscriptionMap::try_get_or_insert`] for bounded
+    /// maps instead.
+    pub async fn get_or_insert(&self, key: K, value: V) -> SubscriptionRef<K, V> {
+        self.get_or_insert_with(key, || value).await
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert`] but returns an error instead of panicking if the
+    /// entry can't be inserted, because the map is closed or full.
+    pub async fn try_get_or_insert(
+        &self,
+        key: K,
+        value: V,
+    ) -> Result<SubscriptionRef<K, V>, InsertError> {
+        self.try_get_or_insert_with(key, || value).await
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert`] but only computes the initial value if the entry
+    /// is actually created. The closure is called while the map is locked.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, see
+    /// [`SubscriptionMap::try_get_or_insert_with`].
+    pub async fn get_or_insert_with<F>(&self, key: K, value: F) -> SubscriptionRef<K, V>
+    where
+        F: FnOnce() -> V,
+    {
+        expect_inserted(self.try_get_or_insert_with(key, value).await)
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert_with`] but returns an error instead of panicking if
+    /// the entry can't be inserted, because the map is closed or full.
+    pub async fn try_get_or_insert_with<F>(
+        &self,
+        key: K,
+        value: F,
+    ) -> Result<SubscriptionRef<K, V>, InsertError>
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(sub) = self.get(&key).await {
+            return Ok(sub);
+        }
+
+        let mut state = self.0.for_key(&key).write().await;
+        let entry = state.try_get_or_insert_with(key, value)?;
+
+        Ok(SubscriptionRef::new(self.clone(), entry).unwrap())
+    }
+
+    /// Subscribe to many keys at once, inserting the missing ones with the given values, like
+    /// [`SubscriptionMap::get_or_insert`] for each pair. Present keys keep their value.
+    ///
+    /// All shards are write locked just once, so seeding a map doesn't take a lock per entry. The
+    /// returned refs keep the entries alive, in the order of the pairs.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for an entry, see
+    /// [`SubscriptionMap::try_extend`].
+    pub async fn extend<I>(&self, pairs: I) -> Vec<SubscriptionRef<K, V>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        expect_inserted(self.try_extend(pairs).await)
+    }
+
+    /// Like [`SubscriptionMap::extend`] but returns an error instead of panicking if the entry
+    /// can't be inserted, because the map is closed or full.
+    ///
+    /// Pairs are inserted in order up to the failing one. The entries inserted before are cleaned
+    /// up again, unless they were present already or are still in use.
+    pub async fn try_extend<I>(&self, pairs: I) -> Result<Vec<SubscriptionRef<K, V>>, InsertError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut shards = self.0.write_all().await;
+        let mut subscriptions = Vec::new();
+
+        let inserted = pairs.into_iter().try_for_each(|(key, value)| {
+            let state = &mut shards[self.0.index(&key)];
+            let entry = state.try_get_or_insert_with(key, || value)?;
+            subscriptions.push(SubscriptionRef::new(self.clone(), entry).unwrap());
+            Ok(())
+        });
+
+        // dropping a ref locks the map, so the refs may only be discarded once it is unlocked
+        drop(shards);
+        inserted.map(|()| subscriptions)
+    }
+
+    /// Keep the entry in the map while the returned guard is held, inserting it with the given
+    /// value if missing.
+    ///
+    /// Unlike a subscription ref, the guard doesn't fork an observable or count as a subscriber,
+    /// so producers can keep entries which are expensive to repopulate resident while consumers
+    /// come and go.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, see
+    /// [`SubscriptionMap::try_pin`].
+    pub async fn pin(&self, key: K, value: V) -> PinGuard<K, V> {
+        expect_inserted(self.try_pin(key, value).await)
+    }
+
+    /// Like [`SubscriptionMap::pin`] but returns an error instead of panicking if the entry can't
+    /// be inserted, because the map is closed or full.
+    pub async fn try_pin(&self, key: K, value: V) -> Result<PinGuard<K, V>, InsertError> {
+        let mut state = self.0.for_key(&key).write().await;
+        let entry = state.try_get_or_insert_with(key, || value)?;
+        entry.pins += 1;
+
+        Ok(PinGuard::new(entry.slot.clone(), entry.id, self.clone()))
+    }
+
+    /// Get the entry of the key to conditionally insert, publish to and subscribe to it without
+    /// other tasks interfering in between, like the entry API of the std maps.
+    ///
+    /// The shard of the key is locked for writing until the entry is dropped.
+    pub async fn entry(&self, key: K) -> Entry<'_, K, V> {
+        let state = self.0.for_key(&key).write().await;
+        Entry::new(key, self, state)
+    }
+
+    /// Subscribe to the key only if it is already present in the map. Only takes a shared read
+    /// lock, so concurrent lookups don't contend with each other.
+    pub async fn get<Q>(&self, key: &Q) -> Option<SubscriptionRef<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.0.for_key(key).read().await;
+        let entry = state.entries.get(key)?;
+
+        Some(SubscriptionRef::new(self.clone(), entry).unwrap())
+    }
+
+    /// Subscribe to the key if it is present, like [`SubscriptionMap::get`], but with control
+    /// over what the subscription yields first. User interfaces usually want the current value,
+    /// while consumers syncing changes might skip it or catch up on retained values.
+    pub async fn subscribe_with<Q>(&self, key: &Q, replay: Replay) -> Option<SubscriptionRef<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.0.for_key(key).read().await;
+        let entry = state.entries.get(key)?;
+
+        Some(SubscriptionRef::with_replay(self.clone(), entry, replay).unwrap())
+    }
+
+    /// Subscribe to many keys at once, like [`SubscriptionMap::get`] for each of them. All shards
+    /// are read locked just once, rather than once per key. The result is in the order of `keys`,
+    /// with `None` for keys that aren't present.
+    pub async fn get_many(&self, keys: &[K]) -> Vec<Option<SubscriptionRef<K, V>>> {
+        let shards = self.0.read_all().await;
+
+        keys.iter()
+            .map(|key| {
+                let entry = shards[self.0.index(key)].entries.get(key)?;
+                Some(SubscriptionRef::new(self.clone(), entry).unwrap())
+            })
+            .collect()
+    }
+
+    /// The value currently seen by the subscribers of the key, or `None` if it isn't present. Unlike
+    /// [`SubscriptionMap::get`] no subscription is created, so the entry isn't kept alive.
+    pub async fn get_value<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.0.for_key(key).read().await;
+        state
+            .entries
+            .get(key)
+            .map(|entry| entry.observable.latest())
+    }
+
+    /// The values retained by the entry, oldest first. Empty if the key isn't present or the
+    /// entry doesn't retain values, see [`SubscriptionMapBuilder::history`] and
+    /// [`SubscriptionMap::get_or_insert_buffered`].
+    pub async fn history<Q>(&self, key: &Q) -> Vec<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.0.for_key(key).read().await;
+        state
+            .entries
+            .get(key)
+            .and_then(|entry| entry.history.as_ref())
+            .map(History::to_vec)
+            .unwrap_or_default()
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert_with`] but computes the initial value asynchronously,
+    /// without holding the map lock.
+    ///
+    /// The key is reserved while the initializer runs, so concurrent callers for the same key wait
+    /// for the pending initialization instead of running their own. If the initializing future is
+    /// dropped before completion, one of the waiting callers takes over.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, see
+    /// [`SubscriptionMap::try_get_or_insert_with_async`].
+    pub async fn get_or_insert_with_async<F, Fut>(&self, key: K, init: F) -> SubscriptionRef<K, V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        expect_inserted(self.try_get_or_insert_with_async(key, init).await)
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert_with_async`] but returns an error instead of panicking
+    /// if the entry can't be inserted, because the map is closed or full.
+    pub async fn try_get_or_insert_with_async<F, Fut>(
+        &self,
+        key: K,
+        init: F,
+    ) -> Result<SubscriptionRef<K, V>, InsertError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        loop {
+            let mut membership = {
+                let mut state = self.0.for_key(&key).write().await;
+
+                if let Some(entry) = state.entries.get_mut(&key) {
+                    return Ok(SubscriptionRef::new(self.clone(), entry).unwrap());
+                }
+
+                if state.closed {
+                    return Err(ClosedError.into());
+                }
+
+                if state.initializing.insert(key.clone()) {
+                    break;
+                }
+
+                // the map never synchronizes its own observable, so the fork has to catch up
+                let mut membership = state.membership.fork();
+                membership.synchronize();
+                membership
+            };
+
+            membership.next().await;
+        }
+
+        let reservation = Reservation::new(self.clone(), key.clone());
+        let value = init().await;
+
+        let mut state = self.0.for_key(&key).write().await;
+        reservation.complete(&mut state);
+
+        let entry = state.try_get_or_insert_with(key, || value)?;
+        Ok(SubscriptionRef::new(self.clone(), entry).unwrap())
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert`] but gives up if the map couldn't be locked within
+    /// the given duration.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, see
+    /// [`SubscriptionMap::try_get_or_insert_timeout`].
+    pub async fn get_or_insert_timeout(
+        &self,
+        key: K,
+        value: V,
+        dur: Duration,
+    ) -> Result<SubscriptionRef<K, V>, TimeoutError> {
+        match self.try_get_or_insert_timeout(key, value, dur).await {
+            Err(InsertError::Timeout(e)) => Err(e),
+            inserted => Ok(expect_inserted(inserted)),
+        }
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert_timeout`] but returns an error instead of panicking if
+    /// the entry can't be inserted, because the map is closed or full.
+    pub async fn try_get_or_insert_timeout(
+        &self,
+        key: K,
+        value: V,
+        dur: Duration,
+    ) -> Result<SubscriptionRef<K, V>, InsertError> {
+        let mut state = runtime::timeout(dur, self.0.for_key(&key).write())
+            .await
+            .ok_or_else(|| TimeoutError::new(dur))?;
+
+        let entry = state.try_get_or_insert_with(key, || value)?;
+        Ok(SubscriptionRef::new(self.clone(), entry).unwrap())
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert`] but retains the last `history` published values of
+    /// the entry, starting with its initial value. They can be replayed by every subscriber, see
+    /// [`SubscriptionRef::replay`].
+    ///
+    /// Values are only retained during the lifetime of the entry, so the history starts over if
+    /// the entry is cleaned up and recreated later on.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, see
+    /// [`SubscriptionMap::try_get_or_insert_buffered`].
+    pub async fn get_or_insert_buffered(
+        &self,
+        key: K,
+        value: V,
+        history: usize,
+    ) -> SubscriptionRef<K, V> {
+        expect_inserted(self.try_get_or_insert_buffered(key, value, history).await)
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert_buffered`] but returns an error instead of panicking
+    /// if the entry can't be inserted, because the map is closed or full.
+    pub async fn try_get_or_insert_buffered(
+        &self,
+        key: K,
+        value: V,
+        history: usize,
+    ) -> Result<SubscriptionRef<K, V>, InsertError> {
+        let mut state = self.0.for_key(&key).write().await;
+        let entry = state.try_get_or_insert_with(key, || value)?;
+
+        let history = entry
+            .history
+            .is_none()
+            .then(|| entry.retain_history(history));
+        let entry_state = entry.state.clone();
+        let subscription = SubscriptionRef::new(self.clone(), entry).unwrap();
+
+        drop(state);
+
+        if let Some(history) = history {
+            entry_state.lock().await.retain_history(history);
+        }
+
+        Ok(subscription)
+    }
+
+    /// Wait until the key is present in the map and subscribe to it.
+    ///
+    /// Resolves immediately if the key already exists. Entries which are created and cleaned up
+    /// again before this task got the chance to subscribe are ignored and waiting continues.
+    ///
+    /// Waiting only takes the shared read lock, so any number of consumers can wait for keys
+    /// without contending with each other or with lookups. Fails once the map is closed, as the
+    /// key will never show up, see [`SubscriptionMap::close`].
+    pub async fn wait_for(&self, key: K) -> Result<SubscriptionRef<K, V>, ClosedError> {
+        loop {
+            let mut membership = {
+                let state = self.0.for_key(&key).read().await;
+
+                if let Some(entry) = state.entries.get(&key) {
+                    return Ok(SubscriptionRef::new(self.clone(), entry).unwrap());
+                }
+
+                if state.closed {
+                    return Err(ClosedError);
+                }
+
+                // forked under the lock, so no insertion can slip through before we wait. The
+                // map never synchronizes its own observable, so the fork has to catch up first.
+                let mut membership = state.membership.fork();
+                membership.synchronize();
+                membership
+            };
+
+            membership.next().await;
+        }
+    }
+
+    /// Remove all entries from the map, regardless of outstanding subscriptions.
+    ///
+    /// All entries are evicted, see [`SubscriptionMap::evict`]. Use
+    /// [`SubscriptionMap::clear_with`] to keep entries still in use instead.
+    pub async fn clear(&self) {
+        for mut state in self.0.write_all().await {
+            if !state.entries.is_empty() {
+                for (key, entry) in state.entries.take_all() {
+                    entry.evict();
+                    state.events.emit(MapEvent::Removed(key));
+                }
+
+                state.notify_membership();
+            }
+        }
+    }
+
+    /// Shut the map down: all entries are evicted and no new ones are inserted anymore.
+    ///
+    /// Subscribers are woken up like for [`SubscriptionMap::evict`], so tasks waiting via
+    /// [`SubscriptionRef::next_or_closed`] return, and so are tasks waiting for keys via
+    /// [`SubscriptionMap::wait_for`], which fail with a [`ClosedError`]. Publishing fails
+    /// afterwards, as no key is present, and nothing is inserted anymore: the `try_` methods,
+    /// like [`SubscriptionMap::try_get_or_insert`], return [`InsertError::Closed`] and the other
+    /// inserting methods panic.
+    pub async fn close(&self) {
+        for mut state in self.0.write_all().await {
+            state.closed = true;
+
+            for (key, entry) in state.entries.take_all() {
+                entry.evict();
+                state.events.emit(MapEvent::Removed(key));
+            }
+
+            // wakes up tasks waiting for keys even if the shard was empty
+            state.notify_membership();
+        }
+    }
+
+    /// Check if the map was shut down, see [`SubscriptionMap::close`]
+    pub async fn is_closed(&self) -> bool {
+        // all shards are closed at once
+        let shard = self.0.iter().next().expect("a map has at least one shard");
+        shard.read().await.closed
+    }
+
+    /// Remove the entry regardless of outstanding subscriptions and return whether it was present.
+    ///
+    /// Subscribers are woken up by publishing the last value once more and can use
+    /// [`SubscriptionRef::is_evicted`] to tell that the entry is gone. Their refs stay valid but
+    /// won't receive any further updates through the map; dropping them is a no-op.
+    pub async fn evict<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let mut state = self.0.for_key(key).write().await;
+
+        match state.entries.get(key).map(|entry| entry.slot.get()) {
+            Some(key) => state.evict(&key),
+            None => false,
+        }
+    }
+
+    /// Remove all entries for which the predicate returns `false` and return how many were
+    /// removed. The predicate is called with the key, the latest published value and the number of
+    /// subscribers of every entry which may be removed.
+    ///
+    /// Pinned entries are always kept. Subscribed entries are only considered if
+    /// `evict_subscribed` is set, their subscribers are notified like for
+    /// [`SubscriptionMap::evict`]. Persistent entries are removed like all others. Locks all
+    /// shards while the predicate runs.
+    pub async fn retain<F>(&self, evict_subscribed: bool, mut keep: F) -> usize
+    where
+        F: FnMut(&K, &V, usize) -> bool,
+    {
+        let mut removed = 0;
+
+        for mut state in self.0.write_all().await {
+            let doomed: Vec<K> = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.pins == 0 && (evict_subscribed || entry.rc.is_zero()))
+                .filter(|(key, entry)| !keep(key, &entry.observable.latest(), entry.rc.get()))
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for key in doomed {
+                state.evict(&key);
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    /// Move the entry to a new key, which must not be present yet. The entry keeps its value,
+    /// subscriptions and pins: existing subscription refs, pin guards and weak refs follow the
+    /// entry to its new key and clean it up there once dropped.
+    ///
+    /// Locks all shards, listeners of [`SubscriptionMap::events`] see the old key removed and the
+    /// new one inserted, followed by a first subscriber event if the entry is subscribed to.
+    pub async fn rename(&self, old: &K, new: K) -> anyhow::Result<()> {
+        let mut shards = self.0.write_all().await;
+        let (from, to) = (self.0.index(old), self.0.index(&new));
+
+        anyhow::ensure!(
+            !shards[to].entries.contains_key(&new) && !shards[to].initializing.contains(&new),
+            "unable rename key {:?} to already present key {:?}",
+            old,
+            new
+        );
+
+        let entry = shards[from]
+            .entries
+            .remove(old)
+            .with_context(|| format!("unable rename not present key {:?}", old))?;
+
+        if let Err(e) = shards[to].make_room(&new) {
+            shards[from].entries.insert(old.clone(), entry);
+            return Err(e).with_context(|| format!("unable rename key {:?} to {:?}", old, new));
+        }
+
+        // handles look the key up again once they locked its shard, which they can't right now
+        entry.slot.set(new.clone());
+        let subscribed = !entry.rc.is_zero();
+        shards[to].entries.insert(new.clone(), entry);
+
+        shards[from].notify_membership();
+        if from != to {
+            shards[to].notify_membership();
+        }
+
+        let events = self.0.events();
+        events.emit(MapEvent::Removed(old.clone()));
+        events.emit(MapEvent::Inserted(new.clone()));
+        if subscribed {
+            events.emit(MapEvent::FirstSubscriber(new));
+        }
+
+        Ok(())
+    }
+
+    /// A stream of lifecycle events of all entries, e.g. to start and stop upstream feeds once
+    /// someone starts or stops caring about a key.
+    ///
+    /// Only events occurring after calling this method are yielded, entries already present
+    /// aren't announced. Events are buffered without bound until the stream is polled, so the
+    /// stream should be consumed continuously or dropped.
+    pub fn events(&self) -> impl Stream<Item = MapEvent<K>> {
+        self.0.events().listen()
+    }
+
+    /// A stream of keys entering and leaving the map. Starts with all keys present once the
+    /// stream is polled first, followed by every key inserted or removed afterwards, no matter
+    /// how short lived. Buffered without bound like [`SubscriptionMap::events`].
+    pub fn key_stream(&self) -> impl Stream<Item = KeyEvent<K>> {
+        let map = self.clone();
+
+        stream::once(async move {
+            // listening under the lock, so no key is missed or announced twice
+            let shards = map.0.read_all().await;
+            let events = map.0.events().listen();
+
+            let mut present: Vec<K> = shards
+                .iter()
+                .flat_map(|state| state.entries.iter().map(|(key, _)| key.clone()))
+                .collect();
+
+            drop(shards);
+
+            if map.is_sharded() {
+                present.sort();
+            }
+
+            let changes = events.filter_map(|event| future::ready(KeyEvent::from_event(event)));
+            stream::iter(present.into_iter().map(KeyEvent::Inserted)).chain(changes)
+        })
+        .flatten()
+    }
+
+    /// Iterate over the keys currently present in the map, in ascending order unless the map was
+    /// created via [`SubscriptionMap::hashed`].
+    ///
+    /// See [`Keys`] for the consistency guarantees of the iterator. To follow the changes of all
+    /// entries, including ones inserted later on, use [`SubscriptionMap::all_changes`] rather than
+    /// subscribing to every key by hand.
+    pub fn keys(&self) -> Keys<K, V> {
+        Keys::new(self.clone(), ..)
+    }
+
+    /// Iterate over the keys within the range, like [`SubscriptionMap::keys`]. Ordered maps only
+    /// look at keys within the range, e.g. to find a window of timestamped or prefixed keys
+    /// without walking the whole map.
+    pub fn keys_in<R>(&self, range: R) -> Keys<K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        Keys::new(self.clone(), range)
+    }
+
+    /// Collect all keys in ascending order. In contrast to [`SubscriptionMap::keys`] every shard
+    /// is locked just once and all shards are held until the keys are collected, so the result is
+    /// a consistent view of the map and much cheaper for large maps.
+    pub async fn snapshot_keys(&self) -> Vec<K> {
+        let shards = self.0.read_all().await;
+
+        let mut keys: Vec<K> = shards
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        // neither shards nor hashed storage keep a global order, runs of sorted keys are cheap
+        keys.sort();
+
+        keys
+    }
+
+    /// Iterate over the values currently seen by subscribers, in the order of their keys, without
+    /// subscribing to them. Locks the map like [`SubscriptionMap::keys`].
+    pub fn values(&self) -> Values<K, V> {
+        Values::new(self.clone())
+    }
+
+    /// Iterate over the keys and values of the map, see [`SubscriptionMap::values`]
+    pub fn iter(&self) -> Iter<K, V> {
+        Iter::new(self.clone())
+    }
+
+    /// Subscribe to every entry currently present in the map at once.
+    ///
+    /// All subscriptions are created under a single lock, so no entry can be cleaned up during
+    /// the sweep. Note that this keeps every entry alive until the yielded refs are dropped.
+    ///
+    /// Entries inserted afterwards aren't covered, see [`SubscriptionMap::all_changes`] for a
+    /// single stream of the changes of all present and future entries.
+    pub async fn subscriptions(&self) -> impl Iterator<Item = (K, SubscriptionRef<K, V>)> {
+        let shards = self.0.read_all().await;
+
+        let mut subscriptions: Vec<_> = shards
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .map(|(key, entry)| {
+                let sub = SubscriptionRef::new(self.clone(), entry).unwrap();
+                (key.clone(), sub)
+            })
+            .collect();
+
+        drop(shards);
+
+        if self.is_sharded() {
+            subscriptions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+
+        subscriptions.into_iter()
+    }
+
+    fn is_sharded(&self) -> bool {
+        self.0.len() > 1
+    }
+
+    /// Turn a persistent entry, e.g. one seeded via [`From`] or [`FromIterator`], into a regular
+    /// one. It is removed immediately if no one subscribes to it or otherwise as soon as the last
+    /// subscription ref is dropped.
+    pub async fn release<Q>(&self, key: &Q) -> anyhow::Result<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+    {
+        let mut state = self.0.for_key(key).write().await;
+        let entry = state
+            .entries
+            .get_mut(key)
+            .with_context(|| format!("unable release not present key {:?}", key))?;
+
+        entry.persistent = false;
+
+        if entry.is_unused() {
+            let key = entry.slot.get();
+            state.remove_unused(&key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Publish the value to the entry, even if it equals the current one. Useful for event like
+    /// values, where duplicates are meaningful, and for values which can't be compared. See
+    /// [`SubscriptionMap::publish_if_changed`] to skip unchanged values.
+    ///
+    /// Returns the number of live subscribers of the entry, e.g. to stop producing values nobody
+    /// listens to.
+    pub async fn publish<Q>(&self, key: &Q, value: V) -> anyhow::Result<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable publish to not present key {:?}", key))?;
+
+        let mut state = EntryState::lock_admitted(&state).await?;
+        state.publish(value);
+
+        Ok(state.subscribers())
+    }
+
+    /// Publish the value to the entry like [`SubscriptionMap::publish`] and return its sequence
+    /// number, which subscribers receive along with it via [`SubscriptionRef::next_sequenced`].
+    /// Debounced values are emitted later, the sequence number of the last emitted value is
+    /// returned for them.
+    pub async fn publish_sequenced<Q>(&self, key: &Q, value: V) -> anyhow::Result<u64>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable publish to not present key {:?}", key))?;
+
+        let mut state = EntryState::lock_admitted(&state).await?;
+        state.publish(value);
+
+        Ok(state.version.get())
+    }
+
+    /// Publish the value to the entry like [`SubscriptionMap::publish`] and return the previous
+    /// one, which is read under the same lock, so no other publish can get lost in between.
+    pub async fn replace<Q>(&self, key: &Q, value: V) -> anyhow::Result<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable replace value of not present key {:?}", key))?;
+
+        let mut state = EntryState::lock_admitted(&state).await?;
+        let previous = state.latest();
+        state.publish(value);
+
+        Ok(previous)
+    }
+
+    /// Publish the value like [`SubscriptionMap::publish_sequenced`] and wait until every
+    /// subscription ref of the entry received it, or a newer value, via [`SubscriptionRef::next`]
+    /// or [`SubscriptionRef::next_sequenced`]. Refs dropped in the meantime aren't waited for.
+    /// Useful to ensure a final state was delivered before shutting down.
+    ///
+    /// Returns whether all refs observed the value before the timeout elapsed. Debounced values
+    /// are emitted later, only the last emitted value is waited for then.
+    pub async fn publish_and_wait_observed<Q>(
+        &self,
+        key: &Q,
+        value: V,
+        timeout: Duration,
+    ) -> anyhow::Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+    {
+        let (state, cursors) = {
+            let state = self.0.for_key(key).read().await;
+            let entry = state.entries.get(key);
+            entry.map(|entry| (entry.state.clone(), entry.cursors.clone()))
+        }
+        .with_context(|| format!("unable publish to not present key {:?}", key))?;
+
+        let version = {
+            let mut state = EntryState::lock_admitted(&state).await?;
+            state.publish(value);
+            state.version.get()
+        };
+        let observed = runtime::timeout(timeout, cursors.observed(version)).await;
+
+        Ok(observed.is_some())
+    }
+
+    /// Publish a batch of values to related keys at once, like [`SubscriptionMap::publish`] for
+    /// every pair. All entries are locked before the first value is published and unlocked after
+    /// the last one, so anything reading entries through their locks, e.g.
+    /// [`SubscriptionRef::latest_versioned`], sees either none or all of the updates. Keys not
+    /// present in the map are skipped.
+    ///
+    /// Returns whether each value was published, keys not present are left out of the result.
+    /// Values an entry refuses, e.g. because it exceeded its rate limit or a second value to the
+    /// same entry doesn't fit into a bounded subscriber's buffer, are dropped.
+    pub async fn publish_batch<I>(&self, updates: I) -> Vec<(K, Result<(), PublishError>)>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let updates: Vec<(K, V)> = updates.into_iter().collect();
+        let mut states = self
+            .lock_entry_states(updates.iter().map(|(key, _)| key))
+            .await;
+
+        let mut published = Vec::with_capacity(states.len());
+
+        for (key, value) in updates {
+            if let Some(state) = states.get_mut(&key) {
+                published.push((key, state.try_publish(value)));
+            }
+        }
+
+        published
+    }
+
+    /// Read and write the entries of the given keys atomically, e.g. to keep invariants between
+    /// them like an aggregate derived from its members.
+    ///
+    /// The entries are locked for the whole transaction, see [`SubscriptionMap::publish_batch`],
+    /// and only keys present in the map when it starts can be accessed. Values written by the
+    /// closure are published once it returns `Ok`, if it fails nothing is published at all.
+    ///
+    /// Fails if a written entry refuses its value, e.g. because it exceeded its rate limit, then
+    /// none of the values are published either. Otherwise returns the result of the closure.
+    pub async fn transaction<I, F, R, E>(
+        &self,
+        keys: I,
+        run: F,
+    ) -> Result<Result<R, E>, PublishError>
+    where
+        I: IntoIterator<Item = K>,
+        F: FnOnce(&mut Transaction<K, V>) -> Result<R, E>,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let mut transaction = Transaction::new(self.lock_entry_states(&keys).await);
+
+        let result = match run(&mut transaction) {
+            Ok(result) => result,
+            Err(e) => return Ok(Err(e)),
+        };
+        transaction.commit()?;
+
+        Ok(Ok(result))
+    }
+
+    /// Modify the values of two distinct entries together and publish both results, e.g. to move
+    /// a quantity from one key to another. Both entries are locked until both values are
+    /// published, like in a [`SubscriptionMap::transaction`].
+    ///
+    /// Fails if the keys are equal, one of them isn't present or refuses the value, e.g. because it
+    /// exceeded its rate limit, otherwise returns the result of the closure. Either both values
+    /// are published or none.
+    pub async fn transfer<Q, F, R>(&self, from: &Q, to: &Q, modify: F) -> anyhow::Result<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        F: FnOnce(&mut V, &mut V) -> R,
+    {
+        anyhow::ensure!(from != to, "unable to transfer key {:?} to itself", from);
+
+        let mut states = self.lock_entry_states([from, to]).await;
+        let mut source = states
+            .remove(from)
+            .with_context(|| format!("unable transfer from not present key {:?}", from))?;
+        let mut target = states
+            .remove(to)
+            .with_context(|| format!("unable transfer to not present key {:?}", to))?;
+
+        source.check_admission()?;
+        target.check_admission()?;
+
+        let (mut a, mut b) = (source.latest(), target.latest());
+        let result = modify(&mut a, &mut b);
+
+        source.try_publish(a)?;
+        target.try_publish(b)?;
+
+        Ok(result)
+    }
+
+    /// Like [`SubscriptionMap::publish_if_changed`], but with a custom change detection instead of
+    /// `Eq`, e.g. to skip values within a tolerance of the current one. The closure is called with
+    /// the current and the new value and returns whether the new value is a change worth
+    /// publishing.
+    pub async fn publish_if_changed_with<Q, F>(
+        &self,
+        key: &Q,
+        value: V,
+        changed: F,
+    ) -> anyhow::Result<Option<usize>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        F: FnOnce(&V, &V) -> bool,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable publish new version of not present key {:?}", key))?;
+
+        let (mut state, room) = EntryState::lock_with_room(&state).await;
+        state.take_token()?;
+        let changed = state.publish_if_changed_with(value, changed, room)?;
+
+        Ok(changed.then(|| state.subscribers()))
+    }
+
+    /// Like [`SubscriptionMap::modify_and_publish`], but the closure may fail. The modified value
+    /// is only published if the closure succeeded, on error the entry keeps its previous value and
+    /// subscribers aren't notified.
+    ///
+    /// Fails if the key isn't present, otherwise returns the result of the closure.
+    pub async fn try_modify_and_publish<Q, F, R, E>(
+        &self,
+        key: &Q,
+        modify: F,
+    ) -> anyhow::Result<Result<R, E>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        F: FnOnce(&mut V) -> Result<R, E>,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable modify not present key {:?}", key))?;
+
+        let mut state = EntryState::lock_admitted(&state).await?;
+
+        Ok(state.try_modify(modify))
+    }
+
+    /// The version of the entry, which starts at zero and is incremented with every value
+    /// published to its subscribers.
+    pub async fn current_version<Q>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.entry_state(key).await?;
+        let version = state.lock().await.version.get();
+        Some(version)
+    }
+
+    /// The publishing state of the entry, the map is only locked during the lookup
+    async fn entry_state<Q>(&self, key: &Q) -> Option<Arc<Mutex<EntryState<V>>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.0.for_key(key).read().await;
+        state.entries.get(key).map(|entry| entry.state.clone())
+    }
+
+    /// Lock the states of all present entries of the keys, in ascending key order to prevent
+    /// deadlocks between concurrent callers. The map itself is only locked for the lookup.
+    ///
+    /// Waits until the buffers of all waiting bounded subscribers have room for another value,
+    /// unlocking every entry meanwhile, see [`EntryState::lock_with_room`].
+    async fn lock_entry_states<'a, Q, I>(
+        &self,
+        keys: I,
+    ) -> BTreeMap<K, MutexGuardArc<EntryState<V>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord + 'a,
+        I: IntoIterator<Item = &'a Q>,
+    {
+        let shards = self.0.read_all().await;
+
+        let states: BTreeMap<K, _> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let entry = shards[self.0.index(key)].entries.get(key)?;
+                Some((entry.slot.get(), entry.state.clone()))
+            })
+            .collect();
+
+        drop(shards);
+
+        'lock: loop {
+            let mut guards = BTreeMap::new();
+
+            for (key, state) in &states {
+                let mut guard = state.lock_arc().await;
+
+                if let Ok(Some(full)) = guard.bounded.reserve() {
+                    drop(guard);
+                    drop(guards);
+                    full.wait().await;
+                    continue 'lock;
+                }
+
+                guards.insert(key.clone(), guard);
+            }
+
+            return guards;
+        }
+    }
+
+    /// The number of entries in the map. Shards are counted one after another, so with
+    /// concurrent inserts or removals the result might not match any single point in time.
+    pub async fn len(&self) -> usize {
+        let mut len = 0;
+
+        for shard in self.0.iter() {
+            len += shard.read().await.entries.len();
+        }
+
+        len
+    }
+
+    /// Check if the map has no entries, see [`SubscriptionMap::len`]
+    pub async fn is_empty(&self) -> bool {
+        for shard in self.0.iter() {
+            if !shard.read().await.entries.is_empty() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Check if the key is present in the map, without subscribing to it
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        self.0.for_key(key).read().await.entries.contains_key(key)
+    }
+
+    /// The number of live subscription refs of the entry, or `None` if the key isn't present.
+    /// Pins don't count as subscribers. Useful for producers to throttle or stop work for keys
+    /// nobody watches.
+    pub async fn subscriber_count<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.0.for_key(key).read().await;
+        state.entries.get(key).map(|entry| entry.rc.get())
+    }
+
+    /// How far every subscription ref of the entry is behind its latest version, the slowest
+    /// first, to find consumers stalling a pipeline. Returns `None` if the key isn't present.
+    ///
+    /// A ref counts as up to date when subscribing and advances whenever it receives a value, see
+    /// [`SubscriptionRef::lag`]. Since refs only see the latest value, a lagging ref catches up
+    /// with its next receive, the lag tells how many versions it skipped.
+    pub async fn subscriber_lag<Q>(&self, key: &Q) -> Option<Vec<SubscriberLag>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.0.for_key(key).read().await;
+        let entry = state.entries.get(key)?;
+
+        Some(entry.cursors.lags(entry.version.get()))
+    }
+
+    /// The number of live subscription refs across all entries of the map
+    pub async fn total_subscribers(&self) -> usize {
+        let mut total = 0;
+
+        for shard in self.0.iter() {
+            total += shard
+                .read()
+                .await
+                .entries
+                .iter()
+                .map(|(_, entry)| entry.rc.get())
+                .sum::<usize>();
+        }
+
+        total
+    }
+
+    /// Collect every key together with its current subscription count, mainly useful to assert
+    /// on the subscription accounting in tests.
+    pub async fn debug_snapshot(&self) -> Vec<(K, usize)> {
+        let mut snapshot: Vec<_> = self
+            .0
+            .read_all()
+            .await
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .map(|(key, entry)| (key.clone(), entry.rc.get()))
+            .collect();
+
+        if self.is_sharded() {
+            snapshot.sort();
+        }
+
+        snapshot
+    }
+
+    /// When the entry was created, published to last and how often, e.g. for dashboards showing
+    /// hot and dead keys. Returns `None` if the key isn't present.
+    pub async fn entry_meta<Q>(&self, key: &Q) -> Option<EntryMeta>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.entry_state(key).await?;
+        let state = state.lock().await;
+
+        Some(EntryMeta::new(
+            state.created_at,
+            state.published_at,
+            state.version.get(),
+        ))
+    }
+
+    /// A stream of subscribed keys which weren't published to within the given duration, e.g. to
+    /// alert on dead upstream feeds. Pins and persistence don't count as subscriptions.
+    ///
+    /// Entries are checked periodically, so a key is yielded up to half the duration after it
+    /// became stale. Every key is yielded once per stale period, it is yielded again only after
+    /// it was published to and went stale once more. The stream ends once the map is dropped.
+    pub fn stale_keys(&self, older_than: Duration) -> impl Stream<Item = K> {
+        let interval = (older_than / 2).max(Duration::from_millis(1));
+        // versions of the reported keys, to tell whether they were published to since
+        let reported: BTreeMap<K, u64> = BTreeMap::new();
+
+        stream::unfold(
+            (Arc::downgrade(&self.0), reported, true),
+            move |(map, mut reported, first)| async move {
+                if !first {
+                    runtime::sleep(interval).await;
+                }
+
+                let infos = SubscriptionMap(map.upgrade()?).active_subscriptions().await;
+                reported.retain(|key, version| {
+                    infos.get(key).map(SubscriptionInfo::version) == Some(*version)
+                });
+
+                let mut stale = Vec::new();
+
+                for (key, info) in infos {
+                    let idle =
+                        info.subscribers() > 0 && info.published_at().elapsed() >= older_than;
+
+                    if idle && !reported.contains_key(&key) {
+                        reported.insert(key.clone(), info.version());
+                        stale.push(key);
+                    }
+                }
+
+                Some((stream::iter(stale), (map, reported, false)))
+            },
+        )
+        .flatten()
+    }
+
+    /// Diagnostic information about every entry of the map, e.g. for admin endpoints. The map is
+    /// only locked while collecting the entries, each entry is then locked in turn to read its
+    /// version, so the result isn't an atomic snapshot.
+    pub async fn active_subscriptions(&self) -> BTreeMap<K, SubscriptionInfo> {
+        let entries: Vec<_> = self
+            .0
+            .read_all()
+            .await
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+
+        let mut infos = BTreeMap::new();
+
+        for (key, entry) in entries {
+            let state = entry.state.lock().await;
+            let info = SubscriptionInfo::new(
+                entry.rc.get(),
+                entry.pins,
+                entry.persistent,
+                state.version.get(),
+                state.published_at,
+            );
+            infos.insert(key, info);
+        }
+
+        infos
+    }
+
+    /// A consistent point in time view of all values of the map, as seen by subscribers.
+    ///
+    /// The map and all of its entries are locked until every value is cloned, so neither
+    /// insertions and removals nor publishes can interleave with the snapshot. In contrast
+    /// [`SubscriptionMap::iter`] locks the map only while looking up the next entry.
+    pub async fn snapshot(&self) -> BTreeMap<K, V> {
+        let shards = self.0.read_all().await;
+
+        let states: BTreeMap<K, _> = shards
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .map(|(key, entry)| (key.clone(), entry.state.clone()))
+            .collect();
+
+        // locked in ascending key order like all methods touching multiple entries
+        let mut guards = Vec::with_capacity(states.len());
+        let mut snapshot = BTreeMap::new();
+
+        for (key, state) in states {
+            let state = state.lock_arc().await;
+            snapshot.insert(key, state.observable.latest());
+            guards.push(state);
+        }
+
+        snapshot
+    }
+
+    /// The change detection of the map, which is the same for all of its shards
+    async fn detector(&self) -> Detector<V> {
+        let shard = self.0.iter().next().expect("a map has at least one shard");
+        shard.read().await.detect.clone()
+    }
+
+    #[cfg(test)]
+    async fn entry_snapshot(&self) -> BTreeMap<K, SubscriptionEntry<K, V>> {
+        self.0
+            .read_all()
+            .await
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    #[cfg(test)]
+    async fn remove(&self, key: &K) -> anyhow::Result<()> {
+        self.0.for_key(key).write().await.remove(key)
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone + PartialEq,
+{
+    /// Check if the provided value differs from the observable and publish it if so. Values are
+    /// compared with the change detection of the map, see
+    /// [`SubscriptionMapBuilder::change_detect`], which defaults to `PartialEq`.
+    ///
+    /// Returns the number of live subscribers the value was published to, or `None` if the value
+    /// was unchanged and nothing was published.
+    pub async fn publish_if_changed<Q>(&self, key: &Q, value: V) -> anyhow::Result<Option<usize>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable publish new version of not present key {:?}", key))?;
+
+        let (mut state, room) = EntryState::lock_with_room(&state).await;
+        state.take_token()?;
+        let changed = state.publish_if_changed_with_room(value, room)?;
+
+        Ok(changed.then(|| state.subscribers()))
+    }
+
+    /// Like [`SubscriptionMap::publish_if_changed`], but treats an absent key as a regular
+    /// outcome instead of an error. Useful for publishers which don't care whether anybody is
+    /// subscribed, without racing a separate presence check.
+    pub async fn publish_if_present<Q>(&self, key: &Q, value: V) -> PublishOutcome
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let Some(state) = self.entry_state(key).await else {
+            return PublishOutcome::Absent;
+        };
+
+        let (mut state, room) = EntryState::lock_with_room(&state).await;
+
+        match state.publish_if_admitted(value, room) {
+            Ok(true) => PublishOutcome::Published,
+            Ok(false) => PublishOutcome::Unchanged,
+            Err(e) => PublishOutcome::Refused(e),
+        }
+    }
+
+    /// Publish the new value only if the current one equals `expected`, for optimistic
+    /// concurrency between multiple writers of the same key. The comparison and the publish happen
+    /// under the entry's lock, so no other publish can sneak in between.
+    ///
+    /// Returns whether the new value was published, it isn't if it equals the expected one. On a
+    /// mismatch the actual current value is returned, to retry with. Fails as well if the entry
+    /// refuses the new value, e.g. because it exceeded its rate limit or a bounded subscriber
+    /// rejects it.
+    pub async fn compare_and_publish<Q>(
+        &self,
+        key: &Q,
+        expected: &V,
+        new: V,
+    ) -> Result<bool, CasError<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let state = self.entry_state(key).await.ok_or(CasError::Absent)?;
+        let (mut state, room) = EntryState::lock_with_room(&state).await;
+
+        let current = state.latest();
+        if &current != expected {
+            return Err(CasError::Mismatch(current));
+        }
+
+        state
+            .publish_if_admitted(new, room)
+            .map_err(CasError::Refused)
+    }
+
+    /// Apply a batch of updates, like [`SubscriptionMap::publish_if_changed`] for every pair. All
+    /// entries are locked before the first value is published and unlocked after the last one,
+    /// like in [`SubscriptionMap::publish_batch`]. Returns whether a change was published for
+    /// every updated key, or why the entry refused it, keys not present in the map are skipped and
+    /// left out of the result.
+    pub async fn publish_many<I>(&self, updates: I) -> Vec<(K, Result<bool, PublishError>)>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let updates: Vec<(K, V)> = updates.into_iter().collect();
+        let mut states = self
+            .lock_entry_states(updates.iter().map(|(key, _)| key))
+            .await;
+
+        let mut published = Vec::with_capacity(states.len());
+
+        for (key, value) in updates {
+            if let Some(state) = states.get_mut(&key) {
+                let room = state.try_room();
+                published.push((key, state.publish_if_admitted(value, room)));
+            }
+        }
+
+        published
+    }
+
+    /// Modify the value of the entry and publish the result.
+    ///
+    /// The closure operates on a copy of the current value, which is only published once the
+    /// closure returned. If the closure panics the panic is propagated to the caller, but the entry
+    /// keeps its previous value and the map stays fully usable.
+    ///
+    /// Only the entry is locked while the closure runs, other keys can be published to
+    /// concurrently. Returns the result of the closure, e.g. to read back data derived under the
+    /// lock.
+    pub async fn modify_and_publish<Q, F, R>(&self, key: &Q, modify: F) -> anyhow::Result<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        F: FnOnce(&mut V) -> R,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable modify not present key {:?}", key))?;
+
+        let mut state = EntryState::lock_admitted(&state).await?;
+        let result = state.try_modify(|v| Ok::<_, Infallible>(modify(v)))?;
+
+        Ok(result)
+    }
+
+    /// Like [`SubscriptionMap::modify_and_publish`], but only publishes if the closure actually
+    /// changed the value, according to the change detection of the map. Closures which are
+    /// frequently no-ops don't wake up subscribers this way.
+    ///
+    /// Returns the result of the closure and whether the modified value was published.
+    pub async fn modify_and_publish_if_changed<Q, F, R>(
+        &self,
+        key: &Q,
+        modify: F,
+    ) -> anyhow::Result<(R, bool)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        F: FnOnce(&mut V) -> R,
+    {
+        let state = self
+            .entry_state(key)
+            .await
+            .with_context(|| format!("unable modify not present key {:?}", key))?;
+
+        let (mut state, room) = EntryState::lock_with_room(&state).await;
+        state.take_token()?;
+
+        Ok(state.modify_if_changed(modify, room)?)
+    }
+
+    /// Publish the current values of another map into this one, the other map wins on conflicts.
+    ///
+    /// Keys missing in this map are inserted as persistent entries, see [`FromIterator`], all
+    /// other entries are updated via [`SubscriptionMap::publish_if_changed`]. The other map is
+    /// snapshotted and unlocked before this map is locked, so merging can't deadlock. Keys which
+    /// can't be inserted or whose entry refuses the value, e.g. because it exceeded its rate
+    /// limit, are skipped with a warning.
+    pub async fn merge(&self, other: &SubscriptionMap<K, V>) {
+        let values: Vec<(K, V)> = other
+            .0
+            .read_all()
+            .await
+            .iter()
+            .flat_map(|state| state.entries.iter())
+            .map(|(key, entry)| (key.clone(), entry.observable.latest()))
+            .collect();
+
+        let mut shards = self.0.write_all().await;
+        let mut updates = Vec::new();
+
+        for (key, value) in values {
+            let state = &mut shards[self.0.index(&key)];
+
+            match state.entries.get(&key) {
+                Some(entry) => updates.push((key, entry.state.clone(), value)),
+                None if state.closed => log::warn!("unable to merge key {:?} into closed map", key),
+                None => {
+                    if let Err(e) = state.make_room(&key) {
+                        log::warn!("unable to merge key {:?}: {}", key, e);
+                        continue;
+                    }
+
+                    let id = state.next_id();
+                    let entry =
+                        SubscriptionEntry::persistent(id, key.clone(), value, state.detect.clone())
+                            .with_history(state.history)
+                            .with_debounce(state.debounce.clone())
+                            .with_rate_limit(state.rate_limit.clone());
+                    state.entries.insert(key.clone(), entry);
+                    state.notify_membership();
+                    state.events.emit(MapEvent::Inserted(key));
+                }
+            }
+        }
+
+        drop(shards);
+
+        for (key, state, value) in updates {
+            let (mut state, room) = EntryState::lock_with_room(&state).await;
+
+            if let Err(e) = state.publish_if_admitted(value, room) {
+                log::warn!("unable to merge key {:?}: {}", key, e);
+            }
+        }
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert`] but debounces publishes to the entry: at most one
+    /// value per interval is emitted to subscribers, intermediate values are coalesced and the
+    /// most recent one is always delivered once the interval elapsed.
+    ///
+    /// The interval only applies if the entry is created by this call or wasn't debounced yet.
+    ///
+    /// # Panics
+    ///
+    /// If the map is closed, or full and can't make room for the entry, see
+    /// [`SubscriptionMap::try_get_or_insert_debounced`].
+    pub async fn get_or_insert_debounced(
+        &self,
+        key: K,
+        value: V,
+        interval: Duration,
+    ) -> SubscriptionRef<K, V>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        expect_inserted(self.try_get_or_insert_debounced(key, value, interval).await)
+    }
+
+    /// Like [`SubscriptionMap::get_or_insert_debounced`] but returns an error instead of panicking
+    /// if the entry can't be inserted, because the map is closed or full.
+    pub async fn try_get_or_insert_debounced(
+        &self,
+        key: K,
+        value: V,
+        interval: Duration,
+    ) -> Result<SubscriptionRef<K, V>, InsertError>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let mut state = self.0.for_key(&key).write().await;
+        let entry = state.try_get_or_insert_with(key, || value)?;
+
+        let entry_state = entry.state.clone();
+        let (slot, id) = (entry.slot.clone(), entry.id);
+        let subscription = SubscriptionRef::new(self.clone(), entry).unwrap();
+
+        drop(state);
+
+        let mut entry_state = entry_state.lock().await;
+
+        if entry_state.debounce.is_none() {
+            entry_state.debounce = Some(Debounce::new(interval));
+            spawn_debounce_flush(Arc::downgrade(&self.0), slot, id, interval);
+        }
+
+        drop(entry_state);
+
+        Ok(subscription)
+    }
+}
+
+/// Unwrap the result of an insert for the methods documented to panic if the map is closed or
+/// full
+pub(crate) fn expect_inserted<T>(result: Result<T, InsertError>) -> T {
+    result.unwrap_or_else(|e| panic!("unable to insert into subscription map: {}", e))
+}
+
+/// Periodically emit the pending values of a debounced entry until it is removed from the map
+fn spawn_debounce_flush<K, V>(
+    map: Weak<Shards<K, V>>,
+    slot: Arc<Slot<K>>,
+    id: u64,
+    interval: Duration,
+) where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + PartialEq + Send + Sync + 'static,
+{
+    runtime::spawn(async move {
+        let mut wait = interval;
+
+        loop {
+            runtime::sleep(wait).await;
+
+            let map = match map.upgrade() {
+                Some(map) => map,
+                None => break,
+            };
+
+            let (key, state) = map.read_slot(&slot).await;
+            let state = match state.entries.get(&key) {
+                Some(entry) if entry.id == id => entry.state.clone(),
+                _ => break,
+            };
+
+            wait = match state.lock().await.flush_debounced() {
+                Some(wait) => wait,
+                None => break,
+            };
+        }
+
+        log::trace!("stopped debouncing removed key {:?}", slot.get());
+    });
+}
+
+/// Periodically emit the pending values of all debounced entries until the map is dropped, see
+/// [`SubscriptionMapBuilder::coalesce`] and [`SubscriptionMapBuilder::debounce`]
+fn spawn_debounce_flush_all<K, V>(map: Weak<Shards<K, V>>, interval: Duration)
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + PartialEq + Send + Sync + 'static,
+{
+    runtime::spawn(async move {
+        let mut wait = interval;
+
+        loop {
+            runtime::sleep(wait).await;
+
+            let map = match map.upgrade() {
+                Some(map) => map,
+                None => break,
+            };
+
+            let states: Vec<_> = map
+                .read_all()
+                .await
+                .iter()
+                .flat_map(|state| state.entries.iter())
+                .map(|(_, entry)| entry.state.clone())
+                .collect();
+
+            wait = interval;
+
+            for state in states {
+                if let Some(next) = state.lock().await.flush_debounced() {
+                    wait = wait.min(next);
+                }
+            }
+        }
+
+        log::trace!("stopped coalescing publishes of dropped map");
+    });
+}
+
+/// Periodically remove idle entries and evict stale ones until the map is dropped, see
+/// [`SubscriptionMapBuilder::linger`] and [`SubscriptionMapBuilder::ttl`]
+fn spawn_housekeeping<K, V>(map: Weak<Shards<K, V>>, interval: Duration)
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    runtime::spawn(async move {
+        let mut wait = interval;
+
+        loop {
+            runtime::sleep(wait).await;
+
+            let map = match map.upgrade() {
+                Some(map) => map,
+                None => break,
+            };
+
+            wait = interval;
+
+            for shard in map.iter() {
+                let mut state = shard.write().await;
+
+                for next in [state.sweep(), state.expire()].into_iter().flatten() {
+                    wait = wait.min(next);
+                }
+            }
+        }
+
+        log::trace!("stopped housekeeping of dropped map");
+    });
+}
+
+impl<K, V> Default for SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Seed a map with persistent entries, which are not cleaned up if no one subscribes to them.
+/// Use [`SubscriptionMap::release`] to get rid of them again.
+impl<K, V> FromIterator<(K, V)> for SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut state = MapState::new(Storage::Ordered(BTreeMap::new()));
+
+        for (key, value) in iter {
+            let id = state.next_id();
+            let entry = SubscriptionEntry::persistent(id, key.clone(), value, state.detect.clone());
+            state.entries.insert(key, entry);
+        }
+
+        Self(Arc::new(Shards::single(state)))
+    }
+}
+
+/// Seed a map with persistent entries, see [`FromIterator`]
+impl<K, V> From<BTreeMap<K, V>> for SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn from(map: BTreeMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+/// Seed a map with persistent entries, see [`FromIterator`]
+impl<K, V> From<HashMap<K, V>> for SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn from(map: HashMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+/// A key reserved for asynchronous initialization, which is released again if the initialization
+/// is cancelled.
+struct Reservation<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    map: SubscriptionMap<K, V>,
+    key: Option<K>,
+}
+
+impl<K, V> Reservation<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn new(map: SubscriptionMap<K, V>, key: K) -> Self {
+        Self {
+            map,
+            key: Some(key),
+        }
+    }
+
+    fn complete(mut self, state: &mut MapState<K, V>) {
+        if let Some(key) = self.key.take() {
+            state.initializing.remove(&key);
+            // waiting callers find the entry, or take over if it couldn't be inserted after all
+            state.notify_membership();
+        }
+    }
+}
+
+impl<K, V> Drop for Reservation<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            log::trace!("initialization of key {:?} cancelled", key);
+
+            let mut state = self.map.0.for_key(&key).write_blocking();
+            state.initializing.remove(&key);
+            // wake up waiting callers, so one of them takes over
+            state.notify_membership();
+        }
+    }
+}
+
+/// A transparent wrapper for the underlying subscription in the map
+/// which manages the subscription count and removes the observable if no one
+/// holds a subscription to it.
+///
+/// The first call to `next` on a fresh subscription ref yields the current value of the entry
+/// immediately, all subsequent calls wait for new values to be published.
+#[derive(Debug)]
+#[must_use = "entries are removed as soon as no one subscribes to them"]
+pub struct SubscriptionRef<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    /// Shared with the entry, so the ref keeps finding it after it was renamed
+    slot: Arc<Slot<K>>,
+    id: u64,
+    owner: SubscriptionMap<K, V>,
+    observable: Observable<V>,
+    replay: Vec<V>,
+    /// Retained values yielded before waiting for new ones, see [`Replay::Last`]
+    pending: VecDeque<V>,
+    /// Set until the current value was handed out by the first call to next
+    initial: bool,
+    version: Sequence,
+    /// The version of the value received last, see [`SubscriptionRef::next_sequenced`]
+    seen: Option<u64>,
+    /// Shared with the entry, which reports the lag of every ref
+    cursors: Cursors,
+    cursor: Arc<Cursor>,
+    evicted: Arc<AtomicBool>,
+    rc: Arc<RefCount>,
+    /// Set once the subscription count was decremented, which turns drop into a no-op
+    released: bool,
+}
+
+impl<K, V> SubscriptionRef<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn new(owner: SubscriptionMap<K, V>, entry: &SubscriptionEntry<K, V>) -> anyhow::Result<Self> {
+        let previous = entry
+            .rc
+            .increment()
+            .with_context(|| format!("unable to subscribe to key {:?}", entry.slot.get()))?;
+
+        if previous == 0 {
+            owner
+                .0
+                .events()
+                .emit(MapEvent::FirstSubscriber(entry.slot.get()));
+        }
+
+        Ok(Self {
+            slot: entry.slot.clone(),
+            id: entry.id,
+            owner,
+            // the observable of the entry never publishes itself, so its local version is stale
+            observable: {
+                let mut observable = entry.observable.fork();
+                observable.synchronize();
+                observable
+            },
+            replay: entry
+                .history
+                .as_ref()
+                .map(History::to_vec)
+                .unwrap_or_default(),
+            pending: VecDeque::new(),
+            initial: true,
+            version: entry.version.clone(),
+            seen: None,
+            cursors: entry.cursors.clone(),
+            cursor: entry.cursors.register(entry.version.get()),
+            evicted: entry.evicted.clone(),
+            rc: entry.rc.clone(),
+            released: false,
+        })
+    }
+
+    /// Subscribe to the entry, yielding first what the replay policy asks for
+    fn with_replay(
+        owner: SubscriptionMap<K, V>,
+        entry: &SubscriptionEntry<K, V>,
+        replay: Replay,
+    ) -> anyhow::Result<Self> {
+        let mut subscription = Self::new(owner, entry)?;
+
+        let count = match replay {
+            Replay::Current => return Ok(subscription),
+            Replay::Skip => 0,
+            Replay::Last(count) => count,
+        };
+
+        // the retained values end with the current one, which must not be yielded twice
+        let retained = &subscription.replay;
+        subscription.pending = retained[retained.len().saturating_sub(count)..]
+            .iter()
+            .cloned()
+            .collect();
+
+        if subscription.pending.is_empty() && replay != Replay::Skip {
+            return Ok(subscription);
+        }
+
+        // mark the current value as seen, so only values published afterwards are yielded
+        subscription.initial = false;
+        subscription.observable.synchronize();
+        Ok(subscription)
+    }
+
+    /// The key of the entry, which changes if the entry is renamed, see
+    /// [`SubscriptionMap::rename`]
+    pub fn key(&self) -> K {
+        self.slot.get()
+    }
+
+    /// Derive a view on the subscription which only yields when the mapped value changes, e.g.
+    /// to observe a single condition of a larger value.
+    pub fn map<U, F>(self, map: F) -> MappedSubscription<K, V, U>
+    where
+        U: Clone + Eq,
+        F: Fn(&V) -> U + Send + Sync + 'static,
+    {
+        MappedSubscription::new(self, map)
+    }
+
+    /// Create a handle to the entry which doesn't keep it alive, e.g. for caches. See
+    /// [`WeakSubscriptionRef::upgrade`] to subscribe again.
+    pub fn downgrade(&self) -> WeakSubscriptionRef<K, V> {
+        WeakSubscriptionRef::new(self)
+    }
+
+    /// Explicitly end the subscription, cleaning up the entry if this was the last subscription
+    /// to it. Same as dropping the ref, but makes it obvious where the map is locked.
+    pub fn unsubscribe(mut self) {
+        let owner = self.owner.clone();
+        let (key, mut state) = owner.0.write_slot_blocking(&self.slot);
+        self.release(&key, &mut state);
+    }
+
+    /// Like [`SubscriptionRef::unsubscribe`] but awaits the map lock instead of blocking the
+    /// current thread.
+    pub async fn unsubscribe_async(mut self) {
+        let owner = self.owner.clone();
+        let (key, mut state) = owner.0.write_slot(&self.slot).await;
+        self.release(&key, &mut state);
+    }
+
+    fn release(&mut self, key: &K, state: &mut MapState<K, V>) {
+        self.released = true;
+
+        // a released ref doesn't hold up publishers waiting for it to observe their values
+        self.cursors.advance(&self.cursor, u64::MAX);
+
+        // the entry might have been cleared, or even replaced by a new one of the same key
+        let entry = match state.entries.get_mut(key) {
+            Some(entry) if entry.id == self.id => entry,
+            _ => {
+                log::trace!("subscription ref for removed key {:?} released", key);
+                return;
+            }
+        };
+
+        match entry.rc.decrement() {
+            Ok(1) => state.events.emit(MapEvent::LastUnsubscribed(key.clone())),
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("unable to unsubscribe from key {:?}: {}", key, e);
+                return;
+            }
+        }
+
+        // removal has to happen under the same guard, otherwise a concurrent subscriber could
+        // increment the count in between and trip the assertion in remove
+        if entry.is_unused() {
+            let res = state.remove_unused(key);
+
+            if let Err(e) = res {
+                log::error!("error occurred while cleanup subscription ref {}", e);
+            }
+        }
+    }
+
+    /// Check if the entry was forcefully removed from the map, see [`SubscriptionMap::evict`]. An
+    /// evicted subscription won't receive any further updates.
+    pub fn is_evicted(&self) -> bool {
+        self.evicted.load(Ordering::SeqCst)
+    }
+
+    /// Wait for the next value like `next`, but return an error once the entry is gone for good,
+    /// because it was evicted or the map was closed, see [`SubscriptionMap::close`]. Values
+    /// published right before that might be skipped.
+    pub async fn next_or_closed(&mut self) -> Result<V, ClosedError> {
+        if self.is_evicted() {
+            return Err(ClosedError);
+        }
+
+        let value = self.next().await;
+
+        match self.is_evicted() {
+            true => Err(ClosedError),
+            false => Ok(value),
+        }
+    }
+
+    /// The latest value of the entry together with its version, see
+    /// [`SubscriptionMap::current_version`]. Both are read under the entry lock, so they are
+    /// guaranteed to match. Returns `None` if the entry was removed from the map.
+    pub async fn latest_versioned(&self) -> Option<(u64, V)> {
+        let (key, state) = self.owner.0.read_slot(&self.slot).await;
+        let state = match state.entries.get(&key) {
+            Some(entry) if entry.id == self.id => entry.state.clone(),
+            _ => return None,
+        };
+
+        let state = state.lock().await;
+        Some((state.version.get(), state.observable.latest()))
+    }
+
+    /// Wait for the next value, like the `next` of the observable. Values queued by the replay
+    /// policy of the subscription are yielded first, see [`SubscriptionMap::subscribe_with`].
+    pub async fn next(&mut self) -> V {
+        if let Some(value) = self.pending.pop_front() {
+            return value;
+        }
+
+        if !std::mem::take(&mut self.initial) {
+            self.observable.next().await;
+        }
+
+        // the value received above might be superseded already, yield the latest one instead, so
+        // the cursor records exactly the version this ref received
+        let (version, value) = self.synchronize_versioned();
+        self.cursors.advance(&self.cursor, version);
+        value
+    }
+
+    /// The latest value of the entry, without waiting for a change and without marking it as
+    /// received
+    pub fn latest(&self) -> V {
+        self.observable.latest()
+    }
+
+    /// Skip pending changes and return the latest value, which counts as received, so the next
+    /// call to next waits for a new value
+    pub fn synchronize(&mut self) -> V {
+        self.pending.clear();
+        self.initial = false;
+
+        let (version, value) = self.synchronize_versioned();
+        self.cursors.advance(&self.cursor, version);
+        value
+    }
+
+    /// Mark the latest value as received and return it together with its version
+    fn synchronize_versioned(&mut self) -> (u64, V) {
+        // no value can be published while the version is locked, so both match
+        let version = self.version.lock();
+        (*version, self.observable.synchronize())
+    }
+
+    /// Wait until the value of the entry matches the predicate and return the matching value.
+    ///
+    /// The current value is checked first, even if it was received before, so a condition which
+    /// already holds resolves right away instead of waiting for the next publish. Afterwards every
+    /// new value is checked, since refs only see the latest value intermediate ones might be
+    /// skipped. Values queued by the replay policy are skipped.
+    pub async fn wait_until<F>(&mut self, mut predicate: F) -> V
+    where
+        F: FnMut(&V) -> bool,
+    {
+        let current = self.synchronize();
+
+        if predicate(&current) {
+            return current;
+        }
+
+        loop {
+            let value = self.next().await;
+
+            if predicate(&value) {
+                return value;
+            }
+        }
+    }
+
+    /// Wait for the next value like [`SubscriptionRef::next`], but give up once the duration
+    /// elapsed. The timer runs on the runtime selected via the crate features.
+    pub async fn next_timeout(&mut self, duration: Duration) -> Result<V, Elapsed> {
+        runtime::timeout(duration, self.next())
+            .await
+            .ok_or_else(|| Elapsed::new(duration))
+    }
+
+    /// Wait until the value of the entry matches the predicate like
+    /// [`SubscriptionRef::wait_until`], but give up once the duration elapsed.
+    pub async fn wait_until_timeout<F>(
+        &mut self,
+        predicate: F,
+        duration: Duration,
+    ) -> Result<V, Elapsed>
+    where
+        F: FnMut(&V) -> bool,
+    {
+        runtime::timeout(duration, self.wait_until(predicate))
+            .await
+            .ok_or_else(|| Elapsed::new(duration))
+    }
+
+    /// Wait for the next value like [`SubscriptionRef::next`], together with its sequence number
+    /// and the number of values missed since the previously received one. Subscribers which fall
+    /// behind only see the latest value, the gap tells them to resync. Values queued by the
+    /// replay policy are skipped.
+    pub async fn next_sequenced(&mut self) -> Sequenced<V> {
+        self.pending.clear();
+
+        loop {
+            if !std::mem::take(&mut self.initial) {
+                self.observable.next().await;
+            }
+
+            let (sequence, value) = self.synchronize_versioned();
+
+            // the value received above might have been superseded by one already returned
+            if self.seen.is_some_and(|seen| seen >= sequence) {
+                continue;
+            }
+
+            let missed = match self.seen {
+                Some(seen) => sequence - seen - 1,
+                None => 0,
+            };
+
+            self.seen = Some(sequence);
+            self.cursors.advance(&self.cursor, sequence);
+            return Sequenced::new(sequence, value, missed);
+        }
+    }
+
+    /// Identifies the ref in the lag report of its entry, see [`SubscriptionMap::subscriber_lag`].
+    /// Every clone is a subscriber of its own.
+    pub fn subscriber_id(&self) -> u64 {
+        self.cursor.subscriber()
+    }
+
+    /// The number of versions published to the entry since this ref received a value last, e.g.
+    /// via [`SubscriptionRef::next`].
+    pub fn lag(&self) -> u64 {
+        self.version.get().saturating_sub(self.cursor.received())
+    }
+
+    /// The values retained by the entry at the time of subscribing, oldest first. Empty unless
+    /// the entry was created via [`SubscriptionMap::get_or_insert_buffered`].
+    pub fn replay(&self) -> Vec<V> {
+        self.replay.clone()
+    }
+
+    /// A stream of the values of the entry, to combine the subscription with other streams, e.g.
+    /// via `select!`. Like `next`, the stream yields the current value first and then every new
+    /// one. The subscription is held by the stream and released once it is dropped.
+    pub fn into_stream(self) -> impl Stream<Item = V> {
+        stream::unfold(self, |mut subscription| async move {
+            let value = subscription.next().await;
+            Some((value, subscription))
+        })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<K, V> SubscriptionRef<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Forward every published value into a tokio watch channel.
+    ///
+    /// The subscription is moved into a forwarding task, which keeps the entry alive until all
+    /// receivers are dropped. Needs to be called from within a tokio runtime.
+    pub fn into_watch(mut self) -> tokio::sync::watch::Receiver<V> {
+        let (tx, rx) = tokio::sync::watch::channel(self.synchronize());
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    value = self.next() => {
+                        if tx.send(value).is_err() {
+                            break;
+                        }
+                    }
+                    _ = tx.closed() => break,
+                }
+            }
+
+            log::trace!("watch channel for key {:?} closed", self.slot.get());
+        });
+
+        rx
+    }
+}
+
+/// Cloning a subscription ref counts as another subscription to the entry, which is kept alive
+/// until all clones are dropped. The map isn't locked for cloning.
+///
+/// The observable of the clone is forked, so it continues from the same position as the original
+/// one and yields only values the original didn't consume yet.
+impl<K, V> Clone for SubscriptionRef<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        // the original holds a subscription, so the entry can't be cleaned up concurrently
+        self.rc
+            .increment()
+            .with_context(|| format!("unable to clone subscription to key {:?}", self.key()))
+            .unwrap();
+
+        Self {
+            slot: self.slot.clone(),
+            id: self.id,
+            owner: self.owner.clone(),
+            observable: self.observable.fork(),
+            replay: self.replay.clone(),
+            pending: self.pending.clone(),
+            initial: self.initial,
+            version: self.version.clone(),
+            seen: self.seen,
+            cursors: self.cursors.clone(),
+            cursor: self.cursors.register(self.cursor.received()),
+            evicted: self.evicted.clone(),
+            rc: self.rc.clone(),
+            released: false,
+        }
+    }
+}
+
+impl<K, V> Drop for SubscriptionRef<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+
+        log::trace!("drop for subscription ref for key {:?}", self.slot.get());
+
+        let owner = self.owner.clone();
+        let (key, mut state) = owner.0.write_slot_blocking(&self.slot);
+        self.release(&key, &mut state);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        ApplyDelta, Backpressure, BufferFullError, CapacityError, CasError, ClearMode, ClosedError,
+        Elapsed, Entry, EvictionPolicy, InsertError, KeyEvent, KeyHashing, MapEvent, PublishError,
+        PublishOutcome, RateLimitedError, RefCount, Replay, SharedSubscriptionMap, SubscriptionMap,
+        SubscriptionRef, Update,
+    };
+    use async_std::future::timeout;
+    use async_std::task;
+    use futures::{stream, StreamExt};
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    macro_rules! assert_map_len {
+        ($map:ident, $len:expr) => {
+            assert_eq!($map.entry_snapshot().await.len(), $len);
+        };
+    }
+
+    macro_rules! assert_ref_count {
+        ($map:ident, $key:expr, $rc:expr) => {
+            assert_eq!($map.entry_snapshot().await.get($key).unwrap().rc.get(), $rc);
+        };
+    }
+
+    #[async_std::test]
+    async fn should_immediately_remove_unused() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert_map_len!(map, 0);
+
+        let _ = map.get_or_insert(1, 1).await;
+        assert_map_len!(map, 0);
+
+        let _ = map.get_or_insert(2, 2).await;
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_remove_entries_on_ref_drop() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert_map_len!(map, 0);
+
+        let ref_one = map.get_or_insert(1, 1).await;
+        assert_map_len!(map, 1);
+
+        let ref_two = map.get_or_insert(2, 2).await;
+        assert_map_len!(map, 2);
+
+        drop(ref_one);
+        assert_map_len!(map, 1);
+        assert!(!map.entry_snapshot().await.contains_key(&1));
+        assert!(map.entry_snapshot().await.contains_key(&2));
+
+        drop(ref_two);
+        assert_map_len!(map, 0);
+        assert!(!map.entry_snapshot().await.contains_key(&1));
+        assert!(!map.entry_snapshot().await.contains_key(&2));
+    }
+
+    #[async_std::test]
+    async fn should_keep_track_of_ref_count() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert_map_len!(map, 0);
+
+        let ref_one = map.get_or_insert(1, 1).await;
+        assert_ref_count!(map, &1, 1);
+
+        let ref_two = map.get_or_insert(1, 1).await;
+        assert_ref_count!(map, &1, 2);
+
+        drop(ref_one);
+        assert_ref_count!(map, &1, 1);
+
+        drop(ref_two);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    #[should_panic]
+    async fn shouldnt_remove_if_rc_is_not_zero() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert_map_len!(map, 0);
+
+        let _ref = map.get_or_insert(1, 1).await;
+        assert_ref_count!(map, &1, 1);
+
+        map.remove(&1).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn should_clear_all_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let ref_one = map.get_or_insert(1, 1).await;
+        let ref_two = map.get_or_insert(2, 2).await;
+        assert_map_len!(map, 2);
+
+        map.clear().await;
+        assert_map_len!(map, 0);
+
+        drop(ref_one);
+        drop(ref_two);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn shouldnt_touch_recreated_entry_on_stale_drop() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let stale = map.get_or_insert(1, 1).await;
+        map.clear().await;
+
+        let fresh = map.get_or_insert(1, 2).await;
+        assert_ref_count!(map, &1, 1);
+
+        drop(stale);
+        assert_ref_count!(map, &1, 1);
+
+        drop(fresh);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_expose_debug_snapshot() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert_eq!(map.debug_snapshot().await, vec![]);
+
+        let _ref_one = map.get_or_insert(1, 1).await;
+        let _ref_two = map.get_or_insert(2, 2).await;
+        let _ref_three = map.get_or_insert(2, 2).await;
+
+        assert_eq!(map.debug_snapshot().await, vec![(1, 1), (2, 2)]);
+    }
+
+    #[async_std::test]
+    async fn should_keep_seeded_entries_without_subscribers() {
+        let seed: HashMap<usize, usize> = [(1, 10), (2, 20)].into_iter().collect();
+        let map: SubscriptionMap<usize, usize> = seed.into();
+
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![1, 2]);
+
+        let sub = map.get_or_insert(1, 0).await;
+        assert_eq!(sub.latest(), 10);
+        assert_ref_count!(map, &1, 1);
+
+        drop(sub);
+        assert_ref_count!(map, &1, 0);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[async_std::test]
+    async fn should_remove_released_entries() {
+        let seed: BTreeMap<usize, usize> = [(1, 10), (2, 20)].into_iter().collect();
+        let map: SubscriptionMap<usize, usize> = seed.into();
+
+        map.release(&1).await.unwrap();
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![2]);
+
+        let sub = map.get_or_insert(2, 0).await;
+        map.release(&2).await.unwrap();
+        assert_map_len!(map, 1);
+
+        drop(sub);
+        assert_map_len!(map, 0);
+
+        assert!(map.release(&3).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn should_time_out_if_map_is_locked() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let dur = Duration::from_millis(20);
+
+        let guard = map.0.for_key(&1).write().await;
+        let err = map.get_or_insert_timeout(1, 1, dur).await.unwrap_err();
+        assert_eq!(err.duration(), dur);
+        drop(guard);
+
+        let sub = map.get_or_insert_timeout(1, 1, dur).await.unwrap();
+        assert_eq!(sub.latest(), 1);
+        assert_ref_count!(map, &1, 1);
+    }
+
+    #[async_std::test]
+    async fn should_subscribe_to_all_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let ref_one = map.get_or_insert(1, 10).await;
+        let ref_two = map.get_or_insert(2, 20).await;
+
+        let subscriptions: Vec<_> = map.subscriptions().await.collect();
+        assert_ref_count!(map, &1, 2);
+        assert_ref_count!(map, &2, 2);
+
+        drop(ref_one);
+        drop(ref_two);
+        assert_map_len!(map, 2);
+
+        let values: Vec<_> = subscriptions
+            .iter()
+            .map(|(key, sub)| (*key, sub.latest()))
+            .collect();
+        assert_eq!(values, vec![(1, 10), (2, 20)]);
+
+        drop(subscriptions);
+        assert_map_len!(map, 0);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn should_forward_updates_into_watch_channel() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let mut rx = map.get_or_insert(1, 1).await.into_watch();
+        assert_eq!(*rx.borrow(), 1);
+        assert_ref_count!(map, &1, 1);
+
+        map.publish_if_changed(&1, 2).await.unwrap();
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), 2);
+
+        drop(rx);
+
+        for _ in 0..100 {
+            if map.entry_snapshot().await.is_empty() {
+                break;
+            }
+
+            tokio::task::yield_now().await;
+        }
+
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_debounce_publishes() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let interval = Duration::from_millis(50);
+
+        let sub = map.get_or_insert_debounced(1, 0, interval).await;
+
+        assert!(map.publish_if_changed(&1, 1).await.unwrap().is_some());
+        assert_eq!(sub.latest(), 1);
+
+        assert!(map.publish_if_changed(&1, 2).await.unwrap().is_some());
+        assert!(map.publish_if_changed(&1, 3).await.unwrap().is_some());
+        map.modify_and_publish(&1, |v| *v += 1).await.unwrap();
+        assert_eq!(sub.latest(), 1);
+
+        task::sleep(interval * 3).await;
+        assert_eq!(sub.latest(), 4);
+    }
+
+    #[async_std::test]
+    async fn should_merge_other_map() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let other: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let own = map.get_or_insert(1, 1).await;
+        let _other_one = other.get_or_insert(1, 10).await;
+        let _other_two = other.get_or_insert(2, 20).await;
+
+        map.merge(&other).await;
+
+        assert_eq!(own.latest(), 10);
+        assert_eq!(map.debug_snapshot().await, vec![(1, 1), (2, 0)]);
+        assert_eq!(map.get_or_insert(2, 0).await.latest(), 20);
+        assert_map_len!(map, 2);
+
+        map.merge(&map).await;
+        assert_map_len!(map, 2);
+    }
+
+    #[async_std::test]
+    async fn should_stay_usable_after_modify_panicked() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, 1).await;
+
+        let handle = {
+            let map = map.clone();
+            std::thread::spawn(move || {
+                task::block_on(map.modify_and_publish(&1, |v| {
+                    *v = 42;
+                    panic!("modify closure panicked");
+                }))
+            })
+        };
+
+        assert!(handle.join().is_err());
+        assert_eq!(sub.latest(), 1);
+        assert_ref_count!(map, &1, 1);
+
+        map.modify_and_publish(&1, |v| *v += 1).await.unwrap();
+        assert_eq!(sub.latest(), 2);
+    }
+
+    #[async_std::test]
+    async fn should_replay_buffered_values() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let first = map.get_or_insert_buffered(1, 0, 3).await;
+        assert_eq!(first.replay(), vec![0]);
+
+        for value in 1..=4 {
+            map.publish_if_changed(&1, value).await.unwrap();
+        }
+
+        let second = map.get_or_insert(1, 0).await;
+        assert_eq!(second.replay(), vec![2, 3, 4]);
+        assert_eq!(first.replay(), vec![0]);
+
+        drop(first);
+        drop(second);
+        assert_map_len!(map, 0);
+
+        let recreated = map.get_or_insert_buffered(1, 10, 3).await;
+        assert_eq!(recreated.replay(), vec![10]);
+    }
+
+    #[async_std::test]
+    async fn should_stream_all_changes() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let one = map.get_or_insert(1, 1).await;
+        let mut changes = Box::pin(map.all_changes().await);
+
+        map.publish_if_changed(&1, 2).await.unwrap();
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((1, 2))
+        );
+
+        let two = map.get_or_insert(2, 20).await;
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((2, 20))
+        );
+
+        map.publish_if_changed(&2, 21).await.unwrap();
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((2, 21))
+        );
+
+        drop(one);
+        map.publish_if_changed(&2, 22).await.unwrap();
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((2, 22))
+        );
+
+        drop(two);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_version_publishes() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert_eq!(map.current_version(&1).await, None);
+
+        let sub = map.get_or_insert(1, 1).await;
+        assert_eq!(map.current_version(&1).await, Some(0));
+        assert_eq!(sub.latest_versioned().await, Some((0, 1)));
+
+        assert!(map.publish_if_changed(&1, 1).await.unwrap().is_none());
+        assert_eq!(map.current_version(&1).await, Some(0));
+
+        map.publish_if_changed(&1, 2).await.unwrap();
+        map.modify_and_publish(&1, |v| *v += 1).await.unwrap();
+        assert_eq!(sub.latest_versioned().await, Some((2, 3)));
+
+        map.clear().await;
+        assert_eq!(sub.latest_versioned().await, None);
+    }
+
+    #[async_std::test]
+    async fn should_notify_subscribers_on_evict() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let mut sub = map.get_or_insert(1, 1).await;
+        let other = map.get_or_insert(1, 1).await;
+        assert!(!sub.is_evicted());
+
+        assert_eq!(sub.next().await, 1);
+
+        let waiter = task::spawn(async move {
+            sub.next().await;
+            sub.is_evicted()
+        });
+
+        task::sleep(Duration::from_millis(10)).await;
+        assert!(map.evict(&1).await);
+        assert!(!map.evict(&1).await);
+
+        assert!(timeout(Duration::from_secs(1), waiter).await.unwrap());
+        assert!(other.is_evicted());
+        assert_map_len!(map, 0);
+
+        drop(other);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_evict_entries_on_clear() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let sub = map.get_or_insert(1, 1).await;
+        map.clear().await;
+
+        assert!(sub.is_evicted());
+    }
+
+    #[async_std::test]
+    async fn should_deliver_initial_value_on_first_next() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let mut first = map.get_or_insert(1, 1).await;
+        let next = timeout(Duration::from_millis(100), first.next()).await;
+        assert_eq!(next.unwrap(), 1);
+
+        map.publish_if_changed(&1, 2).await.unwrap();
+
+        let mut second = map.get_or_insert(1, 1).await;
+        let next = timeout(Duration::from_millis(100), second.next()).await;
+        assert_eq!(next.unwrap(), 2);
+
+        let next = timeout(Duration::from_millis(100), first.next()).await;
+        assert_eq!(next.unwrap(), 2);
+
+        assert!(timeout(Duration::from_millis(20), first.next())
+            .await
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn should_publish_many() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let one = map.get_or_insert(1, 1).await;
+        let two = map.get_or_insert(2, 2).await;
+
+        let published = map.publish_many(vec![(1, 10), (2, 2), (3, 30)]).await;
+        assert_eq!(published, vec![(1, Ok(true)), (2, Ok(false))]);
+
+        assert_eq!(one.latest(), 10);
+        assert_eq!(two.latest(), 2);
+        assert_map_len!(map, 2);
+    }
+
+    #[async_std::test]
+    async fn should_support_hashed_backend() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::hashed();
+
+        let one = map.get_or_insert(1, 1).await;
+        let two = map.get_or_insert(2, 2).await;
+        let _three = map.get_or_insert(3, 3).await;
+        assert_ref_count!(map, &1, 1);
+
+        map.publish_if_changed(&2, 20).await.unwrap();
+        assert_eq!(two.latest(), 20);
+
+        let mut keys: Vec<_> = map.keys().collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![1, 2, 3]);
+
+        drop(one);
+        drop(two);
+        assert_map_len!(map, 1);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[async_std::test]
+    async fn should_unsubscribe_explicitly() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let one = map.get_or_insert(1, 1).await;
+        let two = map.get_or_insert(1, 1).await;
+        assert_ref_count!(map, &1, 2);
+
+        one.unsubscribe();
+        assert_ref_count!(map, &1, 1);
+
+        two.unsubscribe_async().await;
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_only_yield_changed_mapped_values() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let mut tens = map.get_or_insert(1, 1).await.map(|v| v / 10);
+        assert_eq!(tens.next().await, 0);
+        assert_ref_count!(map, &1, 1);
+
+        map.publish_if_changed(&1, 3).await.unwrap();
+        map.publish_if_changed(&1, 14).await.unwrap();
+        let next = timeout(Duration::from_millis(100), tens.next()).await;
+        assert_eq!(next.unwrap(), 1);
+        assert_eq!(tens.latest(), 1);
+
+        map.publish_if_changed(&1, 16).await.unwrap();
+        assert!(timeout(Duration::from_millis(20), tens.next())
+            .await
+            .is_err());
+
+        drop(tens);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_count_total_subscribers() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert_eq!(map.total_subscribers().await, 0);
+
+        let one = map.get_or_insert(1, 1).await;
+        let _two = map.get_or_insert(1, 1).await;
+        let _three = map.get_or_insert(2, 2).await;
+        assert_eq!(map.total_subscribers().await, 3);
+
+        drop(one);
+        assert_eq!(map.total_subscribers().await, 2);
+    }
+
+    #[async_std::test]
+    async fn should_only_get_present_keys() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        assert!(map.get(&1).await.is_none());
+        assert_map_len!(map, 0);
+
+        let _one = map.get_or_insert(1, 1).await;
+        let sub = map.get(&1).await.unwrap();
+        assert_eq!(sub.latest(), 1);
+        assert_ref_count!(map, &1, 2);
+    }
+
+    #[async_std::test]
+    async fn should_only_initialize_missing_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let one = map.get_or_insert_with(1, || 1).await;
+        assert_eq!(one.latest(), 1);
+
+        let two = map
+            .get_or_insert_with(1, || unreachable!("entry is already present"))
+            .await;
+        assert_eq!(two.latest(), 1);
+        assert_ref_count!(map, &1, 2);
+    }
+
+    #[async_std::test]
+    async fn should_deduplicate_async_initialization() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let init = |value| {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                task::sleep(Duration::from_millis(20)).await;
+                value
+            }
+        };
+
+        let (one, two) = futures::join!(
+            map.get_or_insert_with_async(1, init(1)),
+            map.get_or_insert_with_async(1, init(2)),
+        );
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(one.latest(), two.latest());
+        assert_ref_count!(map, &1, 2);
+    }
+
+    #[async_std::test]
+    async fn should_take_over_cancelled_async_initialization() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let cancelled = map.get_or_insert_with_async(1, || async {
+            task::sleep(Duration::from_secs(10)).await;
+            1
+        });
+        assert!(timeout(Duration::from_millis(10), cancelled).await.is_err());
+
+        let sub = timeout(
+            Duration::from_secs(1),
+            map.get_or_insert_with_async(1, || async { 2 }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(sub.latest(), 2);
+    }
+
+    #[async_std::test]
+    async fn should_spread_entries_across_shards() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(4);
+
+        let subs: Vec<_> =
+            futures::future::join_all((0..16).map(|i| map.get_or_insert(i, i))).await;
+        assert_map_len!(map, 16);
+        assert_eq!(map.keys().collect::<Vec<_>>(), (0..16).collect::<Vec<_>>());
+
+        let changed = map.publish_many((0..16).map(|i| (i, i + 1))).await;
+        assert!(changed.iter().all(|(_, changed)| *changed == Ok(true)));
+        assert_eq!(subs[7].latest(), 8);
+
+        drop(subs);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_subscribe_concurrently_to_readers() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _one = map.get_or_insert(1, 1).await;
+
+        let guard = map.0.for_key(&1).read().await;
+
+        let sub = timeout(Duration::from_millis(100), map.get_or_insert(1, 2))
+            .await
+            .expect("subscribing to a present entry only needs a read lock");
+        assert_eq!(sub.latest(), 1);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![1]);
+
+        assert!(timeout(Duration::from_millis(10), map.get_or_insert(2, 2))
+            .await
+            .is_err());
+
+        drop(guard);
+        assert_ref_count!(map, &1, 2);
+    }
+
+    #[async_std::test]
+    async fn should_publish_while_other_entry_is_modified() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _one = map.get_or_insert(1, 1).await;
+        let two = map.get_or_insert(2, 2).await;
+
+        let state = map.entry_state(&1).await.unwrap();
+        let guard = state.lock().await;
+
+        let published = timeout(Duration::from_millis(100), map.publish_if_changed(&2, 3))
+            .await
+            .expect("publishing to another key must not wait for the locked entry");
+        assert_eq!(published.unwrap(), Some(1));
+        assert_eq!(two.latest(), 3);
+
+        assert!(
+            timeout(Duration::from_millis(10), map.publish_if_changed(&1, 2))
+                .await
+                .is_err()
+        );
+
+        drop(guard);
+        assert!(map.publish_if_changed(&1, 2).await.unwrap().is_some());
+    }
+
+    #[async_std::test]
+    async fn should_count_cloned_subscriptions() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let mut one = map.get_or_insert(1, 1).await;
+        assert_eq!(one.next().await, 1);
+
+        let mut two = one.clone();
+        assert_ref_count!(map, &1, 2);
+
+        map.publish_if_changed(&1, 2).await.unwrap();
+        assert_eq!(one.next().await, 2);
+        assert_eq!(two.next().await, 2);
+
+        drop(one);
+        assert_ref_count!(map, &1, 1);
+
+        drop(two);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_upgrade_weak_subscriptions_of_present_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let one = map.get_or_insert(1, 1).await;
+        let weak = one.downgrade();
+        assert_ref_count!(map, &1, 1);
+
+        let upgraded = weak.upgrade().await.unwrap();
+        assert_eq!(upgraded.latest(), 1);
+        assert_ref_count!(map, &1, 2);
+
+        drop(one);
+        drop(upgraded);
+        assert_map_len!(map, 0);
+        assert!(weak.upgrade().await.is_none());
+
+        // a recreated entry of the same key is a different entry
+        let _one = map.get_or_insert(1, 2).await;
+        assert!(weak.upgrade().await.is_none());
+    }
+
+    #[async_std::test]
+    async fn should_keep_pinned_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let pin = map.pin(1, 1).await;
+        assert_map_len!(map, 1);
+        assert_ref_count!(map, &1, 0);
+
+        let sub = map.get_or_insert(1, 2).await;
+        assert_eq!(sub.latest(), 1);
+        drop(sub);
+        assert_map_len!(map, 1);
+
+        let sub = map.get(&1).await.unwrap();
+        drop(pin);
+        assert_map_len!(map, 1);
+
+        drop(sub);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_linger_unused_entries() {
+        let map: SubscriptionMap<usize, usize> =
+            SubscriptionMap::with_linger(Duration::from_millis(50));
+
+        let sub = map.get_or_insert(1, 1).await;
+        map.publish_if_changed(&1, 2).await.unwrap();
+        drop(sub);
+        assert_map_len!(map, 1);
+
+        let sub = map.get_or_insert(1, 3).await;
+        assert_eq!(sub.latest(), 2);
+        drop(sub);
+
+        task::sleep(Duration::from_millis(150)).await;
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_evict_stale_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .ttl(Duration::from_millis(100))
+            .build();
+
+        let mut stale = map.get_or_insert(1, 1).await;
+        let fresh = map.get_or_insert(2, 2).await;
+        assert_eq!(stale.next().await, 1);
+
+        for value in 3..6 {
+            task::sleep(Duration::from_millis(40)).await;
+            map.publish_if_changed(&2, value).await.unwrap();
+        }
+
+        timeout(Duration::from_millis(500), stale.next())
+            .await
+            .unwrap();
+        assert!(stale.is_evicted());
+        assert!(!fresh.is_evicted());
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[async_std::test]
+    async fn should_reject_inserts_into_full_map() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .capacity(2, EvictionPolicy::Reject)
+            .build();
+
+        let _one = map.try_get_or_insert(1, 1).await.unwrap();
+        let _two = map.try_get_or_insert(2, 2).await.unwrap();
+
+        let err = map.try_get_or_insert(3, 3).await.unwrap_err();
+        assert_eq!(err, InsertError::Full(CapacityError::new(2)));
+
+        // present keys can still be subscribed to
+        assert!(map.try_get_or_insert(1, 1).await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn should_split_capacity_between_shards() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .shards(4)
+            .capacity(10, EvictionPolicy::Reject)
+            .build();
+
+        let mut subs = Vec::new();
+        for key in 0..100 {
+            if let Ok(sub) = map.try_get_or_insert(key, key).await {
+                subs.push(sub);
+            }
+        }
+
+        // every shard is full, without exceeding the capacity of the whole map
+        assert_eq!(subs.len(), 10);
+        assert_map_len!(map, 10);
+    }
+
+    #[async_std::test]
+    async fn should_build_single_shard_for_zero_shards() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .shards(0)
+            .capacity(2, EvictionPolicy::Reject)
+            .build();
+
+        let _one = map.get_or_insert(1, 1).await;
+        let _two = map.get_or_insert(2, 2).await;
+        assert!(map.try_get_or_insert(3, 3).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn should_wake_waiting_initializers_if_inserting_fails() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .capacity(1, EvictionPolicy::Reject)
+            .build();
+        let _full = map.pin(1, 1).await;
+
+        let first = {
+            let map = map.clone();
+            task::spawn(async move {
+                map.try_get_or_insert_with_async(2, || async {
+                    task::sleep(Duration::from_millis(20)).await;
+                    2
+                })
+                .await
+                .is_err()
+            })
+        };
+
+        task::sleep(Duration::from_millis(5)).await;
+        let second = {
+            let map = map.clone();
+            task::spawn(async move {
+                map.try_get_or_insert_with_async(2, || async { 3 })
+                    .await
+                    .is_err()
+            })
+        };
+
+        // the waiting caller takes over and fails on its own instead of waiting forever
+        assert!(first.await);
+        assert!(timeout(Duration::from_secs(1), second).await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn should_report_full_map_from_all_try_inserts() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .capacity(1, EvictionPolicy::Reject)
+            .build();
+
+        let _one = map.try_pin(1, 1).await.unwrap();
+        let full = InsertError::Full(CapacityError::new(1));
+
+        assert_eq!(map.try_pin(2, 2).await.unwrap_err(), full);
+        assert_eq!(map.try_get_or_insert_with(2, || 2).await.unwrap_err(), full);
+        assert_eq!(
+            map.try_get_or_insert_with_async(2, || async { 2 })
+                .await
+                .unwrap_err(),
+            full
+        );
+        assert_eq!(
+            map.try_get_or_insert_buffered(2, 2, 4).await.unwrap_err(),
+            full
+        );
+        assert_eq!(
+            map.try_get_or_insert_debounced(2, 2, Duration::from_millis(10))
+                .await
+                .unwrap_err(),
+            full
+        );
+        assert_eq!(
+            map.try_get_or_insert_timeout(2, 2, Duration::from_millis(10))
+                .await
+                .unwrap_err(),
+            full
+        );
+
+        match map.entry(2).await {
+            Entry::Vacant(entry) => assert_eq!(entry.try_insert(2).unwrap_err(), full),
+            Entry::Occupied(_) => panic!("key 2 was inserted into the full map"),
+        }
+
+        // the present key doesn't need room, the other one is rejected
+        let err = map.try_extend([(1, 1), (2, 2)]).await.unwrap_err();
+        assert_eq!(err, full);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[async_std::test]
+    #[should_panic(expected = "subscription map is full")]
+    async fn should_panic_when_pinning_into_full_map() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .capacity(1, EvictionPolicy::Reject)
+            .build();
+
+        let _one = map.pin(1, 1).await;
+        let _two = map.pin(2, 2).await;
+    }
+
+    #[async_std::test]
+    async fn should_evict_least_recently_published_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .capacity(2, EvictionPolicy::EvictLeastRecentlyPublished)
+            .build();
+
+        let _pinned = map.pin(1, 1).await;
+        let two = map.get_or_insert(2, 2).await;
+        let three = map.get_or_insert(3, 3).await;
+        assert_map_len!(map, 2);
+        assert!(two.is_evicted());
+
+        task::sleep(Duration::from_millis(5)).await;
+        map.publish_if_changed(&3, 4).await.unwrap();
+
+        // the pinned entry is never evicted, so the just published one has to go
+        let _four = map.get_or_insert(4, 4).await;
+        assert!(three.is_evicted());
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![1, 4]);
+    }
+
+    #[async_std::test]
+    async fn should_emit_lifecycle_events() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut events = map.events();
+
+        let one = map.get_or_insert(1, 1).await;
+        let two = map.get_or_insert(1, 1).await;
+        drop(one);
+        drop(two);
+        map.evict(&2).await;
+
+        let expected = vec![
+            MapEvent::Inserted(1),
+            MapEvent::FirstSubscriber(1),
+            MapEvent::LastUnsubscribed(1),
+            MapEvent::Removed(1),
+        ];
+
+        for event in expected {
+            assert_eq!(events.next().await, Some(event));
+        }
+
+        drop(map);
+        assert_eq!(events.next().await, None);
+    }
+
+    #[async_std::test]
+    async fn should_run_producers_while_entries_exist() {
+        struct Dropped(Arc<AtomicUsize>);
+
+        impl Drop for Dropped {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::with_producer({
+            let dropped = dropped.clone();
+            move |key| {
+                let guard = Dropped(dropped.clone());
+                stream::once(async move { key * 10 })
+                    .chain(stream::pending())
+                    .map(move |value| {
+                        let _ = &guard;
+                        value
+                    })
+            }
+        });
+
+        let mut sub = map.get_or_insert(1, 0).await;
+
+        timeout(Duration::from_secs(1), async {
+            while sub.next().await != 10 {}
+        })
+        .await
+        .unwrap();
+
+        drop(sub);
+        task::sleep(Duration::from_millis(20)).await;
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_std::test]
+    async fn should_combine_changes_of_many_keys() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _one = map.get_or_insert(1, 1).await;
+        let _two = map.get_or_insert(2, 2).await;
+        let _three = map.get_or_insert(3, 3).await;
+
+        let mut many = map.subscribe_many(vec![3, 1, 4, 1]).await;
+        assert_eq!(many.keys(), &[1, 3]);
+        assert_ref_count!(map, &1, 2);
+        assert_ref_count!(map, &2, 1);
+
+        let mut initial = vec![many.next().await.unwrap(), many.next().await.unwrap()];
+        initial.sort();
+        assert_eq!(initial, vec![(1, 1), (3, 3)]);
+
+        map.publish_if_changed(&2, 20).await.unwrap();
+        map.publish_if_changed(&3, 30).await.unwrap();
+        assert_eq!(many.next().await, Some((3, 30)));
+
+        drop(many);
+        assert_ref_count!(map, &1, 1);
+    }
+
+    #[async_std::test]
+    async fn should_change_group_members_while_streaming() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _one = map.get_or_insert(1, 1).await;
+        let _two = map.get_or_insert(2, 2).await;
+
+        let mut group = map.group();
+        assert!(timeout(Duration::from_millis(10), group.next())
+            .await
+            .is_err());
+
+        assert!(group.add(1).await);
+        assert!(!group.add(1).await);
+        assert!(!group.add(3).await);
+        assert_eq!(group.next().await, (1, 1));
+        assert_ref_count!(map, &1, 2);
+
+        assert!(group.add(2).await);
+        assert!(group.remove(&1));
+        assert_ref_count!(map, &1, 1);
+
+        map.publish_if_changed(&1, 10).await.unwrap();
+        assert_eq!(group.next().await, (2, 2));
+
+        map.publish_if_changed(&2, 20).await.unwrap();
+        assert_eq!(group.next().await, (2, 20));
+        assert_eq!(group.keys().collect::<Vec<_>>(), vec![&2]);
+
+        // only the idle ref lags, the group member received its values through its ref
+        let lags = map.subscriber_lag(&2).await.unwrap();
+        let behind: Vec<_> = lags.iter().map(|lag| lag.behind()).collect();
+        assert_eq!(behind, vec![1, 0]);
+    }
+
+    #[async_std::test]
+    async fn should_stream_changes_within_range() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _one = map.get_or_insert(1, 1).await;
+        let _five = map.get_or_insert(5, 5).await;
+
+        let mut changes = Box::pin(map.subscribe_range(2..10).await);
+
+        map.publish_if_changed(&1, 10).await.unwrap();
+        map.publish_if_changed(&5, 50).await.unwrap();
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((5, 50))
+        );
+
+        let _eleven = map.get_or_insert(11, 11).await;
+        let three = map.get_or_insert(3, 3).await;
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((3, 3))
+        );
+
+        // a removed key is watched again once it is inserted anew
+        drop(three);
+        let _three = map.get_or_insert(3, 30).await;
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((3, 30))
+        );
+
+        map.rename(&11, 4).await.unwrap();
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((4, 11))
+        );
+    }
+
+    #[async_std::test]
+    async fn should_stream_changes_of_subtree() {
+        let map: SubscriptionMap<Vec<&str>, usize> = SubscriptionMap::new();
+        let _kitchen = map.get_or_insert(vec!["sensors", "kitchen"], 0).await;
+        let _humidity = map.get_or_insert(vec!["sensors", "room1", "hum"], 0).await;
+        let _next_room = map.get_or_insert(vec!["sensors", "room2"], 0).await;
+
+        let mut changes = Box::pin(map.subscribe_subtree(vec!["sensors", "room1"]).await);
+
+        let _room = map.get_or_insert(vec!["sensors", "room1"], 1).await;
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((vec!["sensors", "room1"], 1))
+        );
+
+        map.publish_if_changed(&vec!["sensors", "kitchen"], 2)
+            .await
+            .unwrap();
+        let _temperature = map.get_or_insert(vec!["sensors", "room1", "temp"], 3).await;
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((vec!["sensors", "room1", "temp"], 3))
+        );
+
+        map.publish_if_changed(&vec!["sensors", "room2"], 4)
+            .await
+            .unwrap();
+        map.publish_if_changed(&vec!["sensors", "room1", "hum"], 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            timeout(Duration::from_secs(1), changes.next())
+                .await
+                .unwrap(),
+            Some((vec!["sensors", "room1", "hum"], 5))
+        );
+    }
+
+    #[async_std::test]
+    async fn should_stream_subscription_values() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut values = Box::pin(map.get_or_insert(1, 1).await.into_stream());
+
+        assert_eq!(values.next().await, Some(1));
+        map.publish_if_changed(&1, 2).await.unwrap();
+        assert_eq!(values.next().await, Some(2));
+
+        drop(values);
+        assert_map_len!(map, 0);
+    }
+
+    #[async_std::test]
+    async fn should_forward_streams_into_sink() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, 0).await;
+        assert_eq!(sub.next().await, 0);
+
+        stream::iter(vec![1, 2, 3])
+            .map(Ok)
+            .forward(map.sink_for(1))
+            .await
+            .unwrap();
+
+        assert_eq!(sub.next().await, 3);
+        assert_eq!(map.current_version(&1).await, Some(3));
+
+        let missing = stream::iter(vec![Ok(1)]).forward(map.sink_for(2)).await;
+        assert!(missing.is_err());
+    }
+
+    #[async_std::test]
+    async fn should_publish_equal_values() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, 1).await;
+        assert_eq!(sub.next().await, 1);
+
+        map.publish(&1, 1).await.unwrap();
+        assert_eq!(timeout(Duration::from_millis(100), sub.next()).await, Ok(1));
+        assert_eq!(map.current_version(&1).await, Some(1));
+
+        assert!(map.publish(&2, 2).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn should_report_subscribers_when_publishing() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let one = map.get_or_insert(1, 1).await;
+        let _pin = map.pin(2, 2).await;
+
+        assert_eq!(map.publish(&1, 2).await.unwrap(), 1);
+        let _two = one.clone();
+        assert_eq!(map.publish_if_changed(&1, 3).await.unwrap(), Some(2));
+        assert_eq!(map.publish_if_changed(&1, 3).await.unwrap(), None);
+        assert_eq!(map.publish(&2, 3).await.unwrap(), 0);
+    }
+
+    #[async_std::test]
+    async fn should_publish_if_present() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, 1).await;
+
+        assert_eq!(
+            map.publish_if_present(&1, 2).await,
+            PublishOutcome::Published
+        );
+        assert_eq!(
+            map.publish_if_present(&1, 2).await,
+            PublishOutcome::Unchanged
+        );
+        assert_eq!(map.publish_if_present(&2, 2).await, PublishOutcome::Absent);
+        assert_eq!(sub.latest(), 2);
+        assert!(map.get(&2).await.is_none());
+    }
+
+    #[async_std::test]
+    async fn should_compare_and_publish() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, 1).await;
+
+        assert_eq!(map.compare_and_publish(&1, &1, 2).await, Ok(true));
+        assert_eq!(
+            map.compare_and_publish(&1, &1, 3).await,
+            Err(CasError::Mismatch(2))
+        );
+        assert_eq!(map.compare_and_publish(&1, &2, 2).await, Ok(false));
+        assert_eq!(
+            map.compare_and_publish(&2, &1, 2).await,
+            Err(CasError::Absent)
+        );
+        assert_eq!(sub.latest(), 2);
+    }
+
+    #[async_std::test]
+    async fn should_publish_if_changed_with_comparator() {
+        let map: SubscriptionMap<usize, f64> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, 1.0).await;
+        let beyond_threshold = |current: &f64, value: &f64| (current - value).abs() > 0.5;
+
+        let published = map.publish_if_changed_with(&1, 1.2, beyond_threshold).await;
+        assert_eq!(published.unwrap(), None);
+        assert_eq!(sub.latest(), 1.0);
+
+        let published = map.publish_if_changed_with(&1, 2.0, beyond_threshold).await;
+        assert_eq!(published.unwrap(), Some(1));
+        assert_eq!(sub.latest(), 2.0);
+        assert_eq!(map.current_version(&1).await, Some(1));
+    }
+
+    #[async_std::test]
+    async fn should_detect_changes_with_configured_detection() {
+        let map: SubscriptionMap<usize, (u64, f64)> = SubscriptionMap::builder()
+            .change_detect(|current: &(u64, f64), value: &(u64, f64)| {
+                // ignore the timestamp and small deltas
+                (current.1 - value.1).abs() > 0.5
+            })
+            .build();
+        let sub = map.get_or_insert(1, (0, 1.0)).await;
+
+        assert_eq!(map.publish_if_changed(&1, (1, 1.2)).await.unwrap(), None);
+        assert_eq!(map.publish_if_changed(&1, (2, 2.0)).await.unwrap(), Some(1));
+        assert_eq!(sub.latest(), (2, 2.0));
+    }
+
+    #[async_std::test]
+    async fn should_return_result_of_modification() {
+        let map: SubscriptionMap<usize, Vec<usize>> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, vec![1]).await;
+
+        let len = map
+            .modify_and_publish(&1, |v| {
+                v.push(2);
+                v.len()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(len, 2);
+        assert_eq!(sub.latest(), vec![1, 2]);
+    }
+
+    #[async_std::test]
+    async fn should_roll_back_failed_modification() {
+        let map: SubscriptionMap<usize, Vec<usize>> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, vec![1]).await;
+
+        let failed = map
+            .try_modify_and_publish(&1, |v| {
+                v.push(2);
+                Err::<(), _>("rejected")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(failed, Err("rejected"));
+        assert_eq!(sub.latest(), vec![1]);
+        assert_eq!(map.current_version(&1).await, Some(0));
+
+        let modified = map
+            .try_modify_and_publish(&1, |v| {
+                v.push(3);
+                Ok::<_, ()>(v.len())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(modified, Ok(2));
+        assert_eq!(sub.latest(), vec![1, 3]);
+        assert!(map
+            .try_modify_and_publish(&2, |_| Ok::<_, ()>(()))
+            .await
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn should_skip_modifications_without_change() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _sub = map.get_or_insert(1, 1).await;
+
+        let unchanged = map.modify_and_publish_if_changed(&1, |v| *v = (*v).max(1));
+        assert_eq!(unchanged.await.unwrap(), ((), false));
+        assert_eq!(map.current_version(&1).await, Some(0));
+
+        let changed = map.modify_and_publish_if_changed(&1, |v| {
+            *v += 1;
+            *v
+        });
+        assert_eq!(changed.await.unwrap(), (2, true));
+        assert_eq!(map.current_version(&1).await, Some(1));
+    }
+
+    #[async_std::test]
+    async fn should_publish_batch_under_entry_locks() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(4);
+        let subs: Vec<_> = stream::iter(0..50)
+            .then(|key| map.get_or_insert(key, 0))
+            .collect()
+            .await;
+
+        let published = map
+            .publish_batch((0..60).rev().map(|key| (key, key + 1)))
+            .await;
+
+        assert_eq!(published.len(), 50);
+        assert!(published.iter().all(|(_, result)| result.is_ok()));
+        for (key, sub) in subs.iter().enumerate() {
+            assert_eq!(sub.latest(), key + 1);
+            assert_eq!(map.current_version(&key).await, Some(1));
+        }
+    }
+
+    #[async_std::test]
+    async fn should_commit_transactions_atomically() {
+        let map: SubscriptionMap<&str, usize> = SubscriptionMap::new();
+        let total = map.get_or_insert("total", 3).await;
+        let a = map.get_or_insert("a", 1).await;
+        let _b = map.get_or_insert("b", 2).await;
+
+        let aborted = map
+            .transaction(["a", "total"], |txn| {
+                txn.set("a", 10);
+                Err::<(), _>("abort")
+            })
+            .await;
+        assert_eq!(aborted, Ok(Err("abort")));
+        assert_eq!(a.latest(), 1);
+
+        let committed = map
+            .transaction(["a", "b", "total", "missing"], |txn| {
+                assert!(!txn.set("missing", 1));
+                txn.set("a", txn.get(&"a").unwrap() + 4);
+                let sum = txn.get(&"a").unwrap() + txn.get(&"b").unwrap();
+                txn.set("total", sum);
+                Ok::<_, ()>(txn.keys().count())
+            })
+            .await;
+
+        assert_eq!(committed, Ok(Ok(3)));
+        assert_eq!(a.latest(), 5);
+        assert_eq!(total.latest(), 7);
+        assert_eq!(map.current_version(&"b").await, Some(0));
+    }
+
+    #[async_std::test]
+    async fn should_transfer_between_entries() {
+        let map: SubscriptionMap<&str, u64> = SubscriptionMap::new();
+        let alice = map.get_or_insert("alice", 10).await;
+        let bob = map.get_or_insert("bob", 5).await;
+
+        let moved = map
+            .transfer(&"alice", &"bob", |from, to| {
+                let amount = (*from).min(7);
+                *from -= amount;
+                *to += amount;
+                amount
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(moved, 7);
+        assert_eq!((alice.latest(), bob.latest()), (3, 12));
+        assert!(map.transfer(&"alice", &"alice", |_, _| ()).await.is_err());
+        assert!(map.transfer(&"alice", &"carol", |_, _| ()).await.is_err());
+        assert_eq!(map.current_version(&"alice").await, Some(1));
+    }
+
+    #[async_std::test]
+    async fn should_replace_and_return_previous_value() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, 1).await;
+
+        assert_eq!(map.replace(&1, 2).await.unwrap(), 1);
+        assert_eq!(map.replace(&1, 3).await.unwrap(), 2);
+        assert_eq!(sub.latest(), 3);
+        assert!(map.replace(&2, 1).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn should_rename_entry_with_its_handles() {
+        let map: SubscriptionMap<String, usize> = SubscriptionMap::sharded(4);
+        let mut sub = map.get_or_insert("old".into(), 1).await;
+        let pin = map.pin("old".into(), 1).await;
+        let weak = sub.downgrade();
+        let _other = map.get_or_insert("taken".into(), 2).await;
+
+        assert!(map.rename(&"old".into(), "taken".into()).await.is_err());
+        assert!(map.rename(&"missing".into(), "new".into()).await.is_err());
+        map.rename(&"old".into(), "new".into()).await.unwrap();
+
+        assert!(map.get("old").await.is_none());
+        assert_eq!(
+            (sub.key(), pin.key(), weak.key()),
+            ("new".into(), "new".into(), "new".into())
+        );
+        assert_eq!(sub.next().await, 1);
+
+        map.publish("new", 2).await.unwrap();
+        assert_eq!(sub.next().await, 2);
+        assert_eq!(sub.latest_versioned().await, Some((1, 2)));
+
+        let upgraded = weak.upgrade().await.unwrap();
+        drop((sub, upgraded, pin));
+        assert!(map.get("new").await.is_none());
+        assert!(weak.upgrade().await.is_none());
+    }
+
+    #[async_std::test]
+    async fn should_compose_operations_on_entry() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let sub = map
+            .entry(1)
+            .await
+            .and_publish(5)
+            .await
+            .unwrap()
+            .or_insert(1);
+        assert_eq!(sub.latest(), 1);
+
+        let again = map
+            .entry(1)
+            .await
+            .and_publish(2)
+            .await
+            .unwrap()
+            .or_insert(3);
+        assert_eq!((sub.latest(), again.latest()), (2, 2));
+
+        match map.entry(1).await {
+            Entry::Occupied(entry) => {
+                assert_eq!(entry.subscribers(), 2);
+                assert_eq!(entry.get().await, 2);
+            }
+            Entry::Vacant(_) => panic!("entry should be present"),
+        }
+
+        assert!(matches!(map.entry(2).await, Entry::Vacant(_)));
+        assert!(map.get(&2).await.is_none());
+    }
+
+    #[async_std::test]
+    async fn should_retain_entries_matching_predicate() {
+        let map: SubscriptionMap<(&str, usize), usize> =
+            [(("gone", 1), 1), (("gone", 2), 2), (("kept", 1), 3)]
+                .into_iter()
+                .collect();
+        let _pin = map.pin(("gone", 3), 4).await;
+        let sub = map.get(&("gone", 2)).await.unwrap();
+
+        let removed = map.retain(false, |key, _, _| key.0 != "gone").await;
+        assert_eq!(removed, 1);
+        assert!(!sub.is_evicted());
+
+        let removed = map
+            .retain(true, |key, value, subscribers| {
+                assert_eq!(subscribers, usize::from(*value == 2));
+                key.0 != "gone"
+            })
+            .await;
+        assert_eq!(removed, 1);
+        assert!(sub.is_evicted());
+
+        let keys: Vec<_> = map.keys().collect();
+        assert_eq!(keys, vec![("gone", 3), ("kept", 1)]);
+    }
+
+    #[async_std::test]
+    async fn should_clear_depending_on_mode() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(2);
+        // a persistent entry, a subscribed one, a pinned one and one removed right away
+        map.merge(&[(1, 1)].into_iter().collect()).await;
+        let sub = map.get_or_insert(2, 2).await;
+        let _pin = map.pin(3, 3).await;
+        map.get_or_insert(4, 4).await.unsubscribe();
+
+        assert!(map.clear_with(ClearMode::Fail).await.is_err());
+        assert_eq!(map.keys().count(), 3);
+
+        assert_eq!(map.clear_with(ClearMode::Skip).await.unwrap(), 1);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![2, 3]);
+        assert!(!sub.is_evicted());
+
+        assert_eq!(map.clear_with(ClearMode::Evict).await.unwrap(), 2);
+        assert_eq!(map.keys().count(), 0);
+        assert!(sub.is_evicted());
+        assert_eq!(map.clear_with(ClearMode::Fail).await.unwrap(), 0);
+    }
+
+    #[async_std::test]
+    async fn should_wake_subscribers_when_closed() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(2);
+        let mut sub = map.get_or_insert(1, 1).await;
+        assert_eq!(sub.next_or_closed().await, Ok(1));
+
+        let waiting = task::spawn(async move { sub.next_or_closed().await });
+        task::sleep(Duration::from_millis(10)).await;
+        map.close().await;
+
+        assert_eq!(waiting.await, Err(ClosedError));
+        assert!(map.is_closed().await);
+        assert!(map.publish(&1, 2).await.is_err());
+
+        let err = map.try_get_or_insert(2, 2).await.unwrap_err();
+        assert_eq!(err, InsertError::Closed(ClosedError));
+        assert!(map.get(&2).await.is_none());
+    }
+
+    #[async_std::test]
+    async fn should_wake_wait_for_when_closed() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(2);
+
+        let waiter = {
+            let map = map.clone();
+            task::spawn(async move { map.wait_for(1).await.err() })
+        };
+
+        task::sleep(Duration::from_millis(10)).await;
+        map.close().await;
+
+        let waited = timeout(Duration::from_secs(1), waiter).await.unwrap();
+        assert_eq!(waited, Some(ClosedError));
+        assert_eq!(map.wait_for(2).await.err(), Some(ClosedError));
+    }
+
+    #[async_std::test]
+    async fn should_report_len_and_presence() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(4);
+        assert!(map.is_empty().await);
+        assert_eq!(map.len().await, 0);
+
+        let subs: Vec<_> = stream::iter(0..10)
+            .then(|key| map.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        assert!(!map.is_empty().await);
+        assert_eq!(map.len().await, 10);
+        assert!(map.contains_key(&3).await);
+        assert!(!map.contains_key(&10).await);
+
+        drop(subs);
+        assert!(map.is_empty().await);
+        assert!(!map.contains_key(&3).await);
+    }
+
+    #[async_std::test]
+    async fn should_count_subscribers_of_key() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _pin = map.pin(1, 1).await;
+        assert_eq!(map.subscriber_count(&1).await, Some(0));
+
+        let sub = map.get_or_insert(1, 1).await;
+        let _clone = sub.clone();
+        assert_eq!(map.subscriber_count(&1).await, Some(2));
+        assert_eq!(map.subscriber_count(&2).await, None);
+    }
+
+    #[async_std::test]
+    async fn should_describe_active_subscriptions() {
+        let map: SubscriptionMap<usize, usize> = [(1, 1)].into_iter().collect();
+        let _pin = map.pin(2, 2).await;
+        let sub = map.get_or_insert(2, 2).await;
+        map.publish(&2, 3).await.unwrap();
+
+        let infos = map.active_subscriptions().await;
+        assert_eq!(infos.keys().collect::<Vec<_>>(), vec![&1, &2]);
+
+        let (persistent, pinned) = (infos[&1], infos[&2]);
+        assert!(persistent.is_persistent());
+        assert_eq!((persistent.subscribers(), persistent.version()), (0, 0));
+        assert_eq!(
+            (pinned.subscribers(), pinned.pins(), pinned.version()),
+            (1, 1, 1)
+        );
+        assert!(pinned.published_at() > persistent.published_at());
+        drop(sub);
+    }
+
+    #[async_std::test]
+    async fn should_peek_value_without_subscribing() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let sub = map.get_or_insert(1, 1).await;
+        map.publish(&1, 2).await.unwrap();
+
+        assert_eq!(map.get_value(&1).await, Some(2));
+        assert_eq!(map.get_value(&2).await, None);
+        assert_eq!(map.subscriber_count(&1).await, Some(1));
+
+        drop(sub);
+        assert_eq!(map.get_value(&1).await, None);
+    }
+
+    #[async_std::test]
+    async fn should_iterate_values_and_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(3);
+        let _subs: Vec<_> = stream::iter([3, 1, 2])
+            .then(|key| map.get_or_insert(key, key * 10))
+            .collect()
+            .await;
+
+        assert_eq!(map.values().collect::<Vec<_>>(), vec![10, 20, 30]);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            vec![(1, 10), (2, 20), (3, 30)]
+        );
+
+        let hashed: SubscriptionMap<usize, usize> =
+            SubscriptionMap::builder().hashed().shards(3).build();
+        let _subs: Vec<_> = stream::iter(0..6)
+            .then(|key| hashed.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        let mut entries: Vec<_> = hashed.iter().collect();
+        entries.sort();
+        assert_eq!(entries, (0..6).map(|key| (key, key)).collect::<Vec<_>>());
+    }
+
+    #[async_std::test]
+    async fn should_snapshot_values_consistently() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(4);
+        let _subs: Vec<_> = stream::iter(0..20)
+            .then(|key| map.get_or_insert(key, 0))
+            .collect()
+            .await;
+
+        let batches = {
+            let map = map.clone();
+            task::spawn(async move {
+                for round in 1..=50 {
+                    map.publish_batch((0..20).map(|key| (key, round))).await;
+                }
+            })
+        };
+
+        for _ in 0..20 {
+            let snapshot = map.snapshot().await;
+            let first = snapshot[&0];
+            assert_eq!(snapshot.len(), 20);
+            assert!(snapshot.values().all(|value| *value == first));
+        }
+
+        batches.await;
+        assert_eq!(map.snapshot().await[&19], 50);
+    }
+
+    #[async_std::test]
+    async fn should_diff_against_older_snapshot() {
+        let map: SubscriptionMap<usize, usize> = [(1, 1), (2, 2), (3, 3)].into_iter().collect();
+        let mut older = map.snapshot().await;
+
+        map.release(&1).await.unwrap();
+        map.publish(&2, 20).await.unwrap();
+        map.publish(&3, 3).await.unwrap();
+        map.merge(&[(4, 4)].into_iter().collect()).await;
+
+        let diff = map.diff(&older).await;
+        assert_eq!(diff.removed().iter().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(diff.changed(), &[(2, 20)].into_iter().collect());
+        assert_eq!(diff.added(), &[(4, 4)].into_iter().collect());
+
+        diff.apply_to(&mut older);
+        assert_eq!(older, map.snapshot().await);
+        assert!(map.diff(&older).await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn should_stream_keys_entering_and_leaving() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(2);
+        let present = map.get_or_insert(2, 2).await;
+        let mut keys = Box::pin(map.key_stream());
+
+        assert_eq!(keys.next().await, Some(KeyEvent::Inserted(2)));
+
+        map.get_or_insert(1, 1).await.unsubscribe();
+        drop(present);
+
+        assert_eq!(keys.next().await, Some(KeyEvent::Inserted(1)));
+        assert_eq!(keys.next().await, Some(KeyEvent::Removed(1)));
+        assert_eq!(keys.next().await, Some(KeyEvent::Removed(2)));
+    }
+
+    #[async_std::test]
+    async fn should_iterate_keys_from_both_ends() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(3);
+        let _subs: Vec<_> = stream::iter(1..=5)
+            .then(|key| map.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        assert_eq!(map.keys().rev().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1]);
+        assert_eq!(map.values().next_back(), Some(5));
+
+        let mut keys = map.keys();
+        assert_eq!(keys.next_back(), Some(5));
+        assert_eq!(keys.next(), Some(1));
+        assert_eq!(keys.next_back(), Some(4));
+        assert_eq!(keys.next(), Some(2));
+        assert_eq!(keys.next_back(), Some(3));
+        assert_eq!(keys.next(), None);
+        assert_eq!(keys.next_back(), None);
+    }
+
+    #[async_std::test]
+    async fn should_iterate_keys_in_range() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(3);
+        let _subs: Vec<_> = stream::iter(0..10)
+            .then(|key| map.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        assert_eq!(map.keys_in(3..6).collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert_eq!(map.keys_in(..=2).rev().collect::<Vec<_>>(), vec![2, 1, 0]);
+        assert_eq!(map.keys_in(8..).collect::<Vec<_>>(), vec![8, 9]);
+        assert_eq!(map.keys_in(5..5).count(), 0);
+        assert_eq!(map.keys_in(20..30).count(), 0);
+
+        let hashed: SubscriptionMap<usize, usize> = SubscriptionMap::hashed();
+        let _subs: Vec<_> = stream::iter(0..10)
+            .then(|key| hashed.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        let mut keys: Vec<_> = hashed.keys_in(3..6).collect();
+        keys.sort();
+        assert_eq!(keys, vec![3, 4, 5]);
+    }
+
+    #[async_std::test]
+    async fn should_hint_size_and_stay_exhausted() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(2);
+        let _subs: Vec<_> = stream::iter(0..4)
+            .then(|key| map.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        let mut keys = map.keys();
+        assert_eq!(keys.size_hint(), (0, None));
+        keys.next();
+        assert_eq!(keys.size_hint(), (0, Some(3)));
+        keys.next_back();
+        assert_eq!(keys.size_hint(), (0, Some(2)));
+        assert_eq!(keys.by_ref().count(), 2);
+        assert_eq!(keys.size_hint(), (0, Some(0)));
+
+        let _late = map.get_or_insert(10, 10).await;
+        assert_eq!(keys.next(), None);
+    }
+
+    #[async_std::test]
+    async fn should_snapshot_keys_in_order() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(3);
+        let _subs: Vec<_> = stream::iter((0..6).rev())
+            .then(|key| map.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        assert_eq!(map.snapshot_keys().await, vec![0, 1, 2, 3, 4, 5]);
+
+        let hashed: SubscriptionMap<usize, usize> = SubscriptionMap::hashed();
+        let _subs: Vec<_> = stream::iter((0..6).rev())
+            .then(|key| hashed.get_or_insert(key, key))
+            .collect()
+            .await;
+
+        assert_eq!(hashed.snapshot_keys().await, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[async_std::test]
+    async fn should_get_many_keys_at_once() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(2);
+        let _one = map.get_or_insert(1, 10).await;
+        let _two = map.get_or_insert(2, 20).await;
+
+        let subs = map.get_many(&[2, 3, 1]).await;
+        let values: Vec<_> = subs
+            .iter()
+            .map(|sub| sub.as_ref().map(|sub| sub.latest()))
+            .collect();
+        assert_eq!(values, vec![Some(20), None, Some(10)]);
+
+        assert_ref_count!(map, &1, 2);
+        assert_ref_count!(map, &2, 2);
+    }
+
+    #[async_std::test]
+    async fn should_extend_with_many_entries() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::sharded(2);
+        let _one = map.get_or_insert(1, 10).await;
+
+        let subs = map.extend(vec![(0, 0), (1, 100), (2, 20)]).await;
+        let values: Vec<_> = subs.iter().map(|sub| sub.latest()).collect();
+        assert_eq!(values, vec![0, 10, 20]);
+        assert_map_len!(map, 3);
+        assert_ref_count!(map, &1, 2);
+
+        drop(subs);
+        assert_map_len!(map, 1);
+    }
+
+    #[async_std::test]
+    async fn should_look_up_borrowed_keys() {
+        let map: SubscriptionMap<String, usize> = SubscriptionMap::sharded(2);
+        let mut sub = map.get_or_insert("key".to_string(), 0).await;
+
+        assert!(map.get("key").await.is_some());
+        assert!(map.contains_key("key").await);
+        assert_eq!(map.publish_if_changed("key", 1).await.unwrap(), Some(1));
+        assert_eq!(sub.next().await, 1);
+        map.modify_and_publish("key", |v| *v += 1).await.unwrap();
+        assert_eq!(sub.next().await, 2);
+
+        assert!(map.evict("key").await);
+        assert!(!map.contains_key("key").await);
+        assert!(sub.is_evicted());
+    }
+
+    #[async_std::test]
+    async fn should_store_values_without_debug() {
+        #[derive(Clone, PartialEq)]
+        struct Secret(&'static str);
+
+        let map: SubscriptionMap<usize, Secret> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, Secret("old")).await;
+
+        map.publish_if_changed(&1, Secret("new")).await.unwrap();
+        assert!(sub.next().await == Secret("new"));
+    }
+
+    #[async_std::test]
+    async fn should_hash_keys_with_custom_hasher() {
+        let map: SubscriptionMap<String, usize> = SubscriptionMap::builder()
+            .hasher(KeyHashing::Fx)
+            .shards(2)
+            .build();
+
+        let _sub = map.get_or_insert("key".to_string(), 1).await;
+        assert_eq!(map.get_value("key").await, Some(1));
+        assert!(!map.contains_key("other").await);
+    }
+
+    #[async_std::test]
+    async fn should_share_values_between_subscribers() {
+        let map: SharedSubscriptionMap<usize, Vec<usize>> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, Arc::new(vec![1])).await;
+        let other = map.get(&1).await.unwrap();
+
+        map.publish_shared(&1, vec![1, 2]).await.unwrap();
+        let value = sub.next().await;
+        assert!(Arc::ptr_eq(&value, &other.latest()));
+
+        let len = map
+            .modify_shared_and_publish(&1, |value| {
+                value.push(3);
+                value.len()
+            })
+            .await
+            .unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(*sub.next().await, vec![1, 2, 3]);
+        assert_eq!(*value, vec![1, 2]);
+    }
+
+    #[async_std::test]
+    async fn should_store_trait_objects() {
+        trait Resource: Send + Sync {
+            fn name(&self) -> String;
+        }
+
+        struct File;
+        struct Socket(u16);
+
+        impl Resource for File {
+            fn name(&self) -> String {
+                "file".into()
+            }
+        }
+
+        impl Resource for Socket {
+            fn name(&self) -> String {
+                format!("socket {}", self.0)
+            }
+        }
+
+        let map: SharedSubscriptionMap<usize, dyn Resource> = SubscriptionMap::new();
+        let _file = map.get_or_insert(1, Arc::new(File)).await;
+        let mut socket = map.get_or_insert(2, Arc::new(Socket(80))).await;
+
+        map.publish(&2, Arc::new(Socket(443))).await.unwrap();
+        assert_eq!(socket.next().await.name(), "socket 443");
+
+        let names: Vec<_> = map.values().map(|resource| resource.name()).collect();
+        assert_eq!(names, vec!["file", "socket 443"]);
+    }
+
+    #[async_std::test]
+    async fn should_publish_deltas() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Push(usize);
+
+        impl ApplyDelta<Push> for Vec<usize> {
+            fn apply_delta(&mut self, delta: &Push) {
+                self.push(delta.0);
+            }
+        }
+
+        let map: SubscriptionMap<usize, Vec<usize>> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, vec![1]).await;
+        let mut deltas = map.subscribe_deltas::<Push, _>(&1).await.unwrap();
+
+        map.publish_delta(&1, Push(2)).await.unwrap();
+        assert_eq!(sub.next().await, vec![1, 2]);
+        map.publish(&1, vec![3]).await.unwrap();
+        map.publish_delta(&1, Push(4)).await.unwrap();
+
+        let updates: Vec<_> = (&mut deltas).take(4).collect().await;
+        assert_eq!(
+            updates,
+            vec![
+                Update::Full(vec![1]),
+                Update::Delta(Push(2)),
+                Update::Full(vec![3]),
+                Update::Delta(Push(4)),
+            ]
+        );
+
+        drop(sub);
+        map.evict(&1).await;
+        assert_eq!(deltas.next().await, None);
+    }
+
+    #[async_std::test]
+    async fn should_retain_history_of_every_entry() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder().history(2).build();
+        let _one = map.get_or_insert(1, 0).await;
+        let _two = map.get_or_insert(2, 0).await;
+
+        for value in 1..=3 {
+            map.publish(&1, value).await.unwrap();
+        }
+
+        assert_eq!(map.history(&1).await, vec![2, 3]);
+        assert_eq!(map.history(&2).await, vec![0]);
+        assert!(map.history(&3).await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn should_replay_according_to_policy() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder().history(4).build();
+        let _sub = map.get_or_insert(1, 0).await;
+
+        for value in 1..=3 {
+            map.publish(&1, value).await.unwrap();
+        }
+
+        let mut current = map.subscribe_with(&1, Replay::Current).await.unwrap();
+        let mut skip = map.subscribe_with(&1, Replay::Skip).await.unwrap();
+        let mut last = map.subscribe_with(&1, Replay::Last(2)).await.unwrap();
+        assert!(map.subscribe_with(&2, Replay::Current).await.is_none());
+
+        assert_eq!(current.next().await, 3);
+        assert_eq!(last.next().await, 2);
+        assert_eq!(last.next().await, 3);
+        assert!(timeout(Duration::from_millis(10), skip.next())
+            .await
+            .is_err());
+
+        map.publish(&1, 4).await.unwrap();
+        assert_eq!(skip.next().await, 4);
+        assert_eq!(last.next().await, 4);
+    }
+
+    #[async_std::test]
+    async fn should_detect_missed_sequence_numbers() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, 0).await;
+
+        let first = sub.next_sequenced().await;
+        assert_eq!(
+            (first.sequence(), *first.value(), first.missed()),
+            (0, 0, 0)
+        );
+
+        assert_eq!(map.publish_sequenced(&1, 1).await.unwrap(), 1);
+        let next = sub.next_sequenced().await;
+        assert_eq!((next.sequence(), next.into_value()), (1, 1));
+
+        for value in 2..=4 {
+            map.publish(&1, value).await.unwrap();
+        }
+
+        let lagging = sub.next_sequenced().await;
+        assert_eq!((lagging.sequence(), *lagging.value()), (4, 4));
+        assert_eq!(lagging.missed(), 2);
+        assert!(lagging.has_gap());
+    }
+
+    #[async_std::test]
+    async fn should_track_entry_meta() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _sub = map.get_or_insert(1, 0).await;
+        assert!(map.entry_meta(&2).await.is_none());
+
+        let created = map.entry_meta(&1).await.unwrap();
+        assert_eq!(created.last_published_at(), None);
+        assert_eq!(created.publish_count(), 0);
+
+        map.publish(&1, 1).await.unwrap();
+        map.publish(&1, 2).await.unwrap();
+
+        let published = map.entry_meta(&1).await.unwrap();
+        assert_eq!(published.created_at(), created.created_at());
+        assert!(published.last_published_at().unwrap() >= created.created_at());
+        assert_eq!(published.publish_count(), 2);
+    }
+
+    #[async_std::test]
+    async fn should_report_stale_keys_once() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _stale = map.get_or_insert(1, 0).await;
+        let _fresh = map.get_or_insert(2, 0).await;
+
+        let window = Duration::from_millis(20);
+        let mut stale = Box::pin(map.stale_keys(window));
+
+        task::sleep(window).await;
+        map.publish(&2, 1).await.unwrap();
+        assert_eq!(stale.next().await, Some(1));
+
+        // reported again only after being published to and going stale once more
+        map.publish(&1, 1).await.unwrap();
+        let mut keys: Vec<_> = stale.take(2).collect().await;
+        keys.sort();
+        assert_eq!(keys, vec![1, 2]);
+    }
+
+    #[async_std::test]
+    async fn should_coalesce_publishes_of_every_entry() {
+        let interval = Duration::from_millis(50);
+        let map: SubscriptionMap<usize, usize> =
+            SubscriptionMap::builder().coalesce(interval).build();
+
+        let one = map.get_or_insert(1, 0).await;
+        let two = map.get_or_insert(2, 0).await;
+
+        for value in 1..=3 {
+            map.publish(&1, value).await.unwrap();
+            map.publish(&2, value * 10).await.unwrap();
+        }
+
+        assert_eq!((one.latest(), two.latest()), (1, 10));
+
+        task::sleep(interval * 3).await;
+        assert_eq!((one.latest(), two.latest()), (3, 30));
+    }
+
+    #[async_std::test]
+    async fn should_debounce_until_quiet() {
+        let quiet = Duration::from_millis(50);
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder().debounce(quiet).build();
+        let sub = map.get_or_insert(1, 0).await;
+
+        for value in 1..=3 {
+            map.publish(&1, value).await.unwrap();
+            task::sleep(quiet / 5).await;
+        }
+
+        assert_eq!(sub.latest(), 0);
+
+        task::sleep(quiet * 3).await;
+        assert_eq!(sub.latest(), 3);
+        assert_eq!(map.current_version(&1).await, Some(1));
+    }
+
+    #[async_std::test]
+    async fn should_rate_limit_publishes() {
+        let refill = Duration::from_millis(50);
+        let map: SubscriptionMap<usize, usize> =
+            SubscriptionMap::builder().rate_limit(2, refill).build();
+        let sub = map.get_or_insert(1, 0).await;
+
+        map.publish(&1, 1).await.unwrap();
+        map.publish(&1, 2).await.unwrap();
+
+        let e = map.publish(&1, 3).await.unwrap_err();
+        let limited = e.downcast_ref::<RateLimitedError>().unwrap();
+        assert!(limited.retry_after() <= refill);
+        assert_eq!(sub.latest(), 2);
+
+        task::sleep(refill * 2).await;
+        map.publish(&1, 4).await.unwrap();
+        assert_eq!(sub.latest(), 4);
+
+        // other keys have their own limit
+        let _other = map.get_or_insert(2, 0).await;
+        map.publish(&2, 1).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn should_rate_limit_every_publishing_path() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::builder()
+            .rate_limit(1, Duration::from_secs(60))
+            .build();
+        let _subs: Vec<_> = stream::iter(1..=5)
+            .then(|key| map.get_or_insert(key, 0))
+            .collect()
+            .await;
+
+        assert_eq!(
+            map.publish_if_present(&1, 1).await,
+            PublishOutcome::Published
+        );
+        assert!(matches!(
+            map.publish_if_present(&1, 2).await,
+            PublishOutcome::Refused(PublishError::RateLimited(_))
+        ));
+
+        assert_eq!(map.compare_and_publish(&2, &0, 1).await, Ok(true));
+        assert!(matches!(
+            map.compare_and_publish(&2, &1, 2).await,
+            Err(CasError::Refused(PublishError::RateLimited(_)))
+        ));
+
+        let published = map.publish_batch([(3, 1), (3, 2)]).await;
+        assert!(published[0].1.is_ok());
+        assert!(matches!(published[1].1, Err(PublishError::RateLimited(_))));
+
+        let changed = map.publish_many([(4, 1), (4, 2)]).await;
+        assert_eq!(changed[0].1, Ok(true));
+        assert!(changed[1].1.is_err());
+
+        // the exhausted entry refuses the whole transaction, the other one keeps its token
+        let refused = map
+            .transaction([3, 5], |txn| {
+                txn.set(3, 10);
+                txn.set(5, 10);
+                Ok::<_, ()>(())
+            })
+            .await;
+        assert!(matches!(refused, Err(PublishError::RateLimited(_))));
+        assert_eq!(map.current_version(&5).await, Some(0));
+
+        assert!(map.transfer(&5, &4, |_, _| ()).await.is_err());
+        assert!(matches!(
+            map.entry(2).await.and_publish(3).await,
+            Err(PublishError::RateLimited(_))
+        ));
+        map.publish(&5, 1).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn should_apply_backpressure_on_every_publishing_path() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _subs: Vec<_> = stream::iter(1..=2)
+            .then(|key| map.get_or_insert(key, 0))
+            .collect()
+            .await;
+
+        // holding the current value already, so the buffer is full
+        let mut rejecting = map
+            .subscribe_bounded(&1, 1, Backpressure::Reject)
+            .await
+            .unwrap();
+        let full = PublishError::BufferFull(BufferFullError::new(1));
+
+        assert_eq!(
+            map.publish_if_present(&1, 0).await,
+            PublishOutcome::Unchanged
+        );
+        assert_eq!(
+            map.publish_if_present(&1, 1).await,
+            PublishOutcome::Refused(full)
+        );
+        assert_eq!(
+            map.compare_and_publish(&1, &0, 1).await,
+            Err(CasError::Refused(full))
+        );
+        assert_eq!(map.publish_batch([(1, 1)]).await, vec![(1, Err(full))]);
+        assert_eq!(map.publish_many([(1, 1)]).await, vec![(1, Err(full))]);
+        assert_eq!(
+            map.transaction([1, 2], |txn| {
+                txn.set(1, 1);
+                txn.set(2, 1);
+                Ok::<_, ()>(())
+            })
+            .await,
+            Err(full)
+        );
+        assert!(map.transfer(&2, &1, |_, _| ()).await.is_err());
+        assert!(map.entry(1).await.and_publish(1).await.is_err());
+        assert_eq!(map.current_version(&2).await, Some(0));
+
+        assert_eq!(rejecting.next().await, Some(0));
+        assert_eq!(map.publish_batch([(1, 1)]).await, vec![(1, Ok(()))]);
+
+        // batches wait for room like single publishes
+        let mut waiting = map
+            .subscribe_bounded(&2, 1, Backpressure::Wait)
+            .await
+            .unwrap();
+        let blocked = timeout(Duration::from_millis(50), map.publish_batch([(2, 1)])).await;
+        assert!(blocked.is_err());
+
+        let batch = {
+            let map = map.clone();
+            task::spawn(async move { map.publish_batch([(2, 1)]).await })
+        };
+        assert_eq!(waiting.next().await, Some(0));
+        assert_eq!(batch.await, vec![(2, Ok(()))]);
+        assert_eq!(waiting.next().await, Some(1));
+    }
+
+    #[async_std::test]
+    async fn should_keep_debounced_values_pending_without_room() {
+        let interval = Duration::from_millis(20);
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _sub = map.get_or_insert_debounced(1, 0, interval).await;
+
+        map.publish(&1, 1).await.unwrap();
+        map.publish(&1, 2).await.unwrap();
+
+        let mut bounded = map
+            .subscribe_bounded(&1, 1, Backpressure::Reject)
+            .await
+            .unwrap();
+
+        task::sleep(interval * 3).await;
+        assert_eq!(map.current_version(&1).await, Some(1));
+
+        assert_eq!(bounded.next().await, Some(1));
+        assert_eq!(bounded.next().await, Some(2));
+        assert_eq!(map.current_version(&1).await, Some(2));
+    }
+
+    #[async_std::test]
+    async fn should_apply_backpressure_to_bounded_subscribers() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _sub = map.get_or_insert(1, 0).await;
+
+        let mut rejecting = map
+            .subscribe_bounded(&1, 2, Backpressure::Reject)
+            .await
+            .unwrap();
+
+        map.publish(&1, 1).await.unwrap();
+        let e = map.publish(&1, 2).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<BufferFullError>().unwrap().capacity(), 2);
+
+        assert_eq!(rejecting.next().await, Some(0));
+        map.publish(&1, 2).await.unwrap();
+        drop(rejecting);
+
+        let mut waiting = map
+            .subscribe_bounded(&1, 1, Backpressure::Wait)
+            .await
+            .unwrap();
+        let publisher = {
+            let map = map.clone();
+            task::spawn(async move {
+                for value in 3..=5 {
+                    map.publish(&1, value).await.unwrap();
+                }
+            })
+        };
+
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            received.push(waiting.next().await.unwrap());
+        }
+
+        publisher.await;
+        assert_eq!(received, vec![2, 3, 4, 5]);
+    }
+
+    #[async_std::test]
+    async fn should_not_lock_entry_while_waiting_for_room() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _sub = map.get_or_insert(1, 0).await;
+
+        let mut waiting = map
+            .subscribe_bounded(&1, 1, Backpressure::Wait)
+            .await
+            .unwrap();
+        let publisher = {
+            let map = map.clone();
+            task::spawn(async move { map.publish(&1, 1).await.unwrap() })
+        };
+
+        // the publisher waits for room without holding the entry lock
+        task::sleep(Duration::from_millis(10)).await;
+        let version = timeout(Duration::from_millis(100), map.current_version(&1))
+            .await
+            .unwrap();
+        assert_eq!(version, Some(0));
+
+        assert_eq!(waiting.next().await, Some(0));
+        publisher.await;
+        assert_eq!(waiting.next().await, Some(1));
+    }
+
+    #[async_std::test]
+    async fn should_not_reject_unchanged_values_for_full_buffers() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _sub = map.get_or_insert(1, 0).await;
+
+        let rejecting = map
+            .subscribe_bounded(&1, 1, Backpressure::Reject)
+            .await
+            .unwrap();
+
+        assert_eq!(map.publish_if_changed(&1, 0).await.unwrap(), None);
+        assert_eq!(
+            map.publish_if_changed_with(&1, 0, |a, b| a != b)
+                .await
+                .unwrap(),
+            None
+        );
+        let (_, changed) = map.modify_and_publish_if_changed(&1, |_| {}).await.unwrap();
+        assert!(!changed);
+        assert_eq!(rejecting.len(), 1);
+
+        let e = map.publish_if_changed(&1, 1).await.unwrap_err();
+        assert!(e.downcast_ref::<BufferFullError>().is_some());
+    }
+
+    #[async_std::test]
+    async fn should_report_subscriber_lag() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut fast = map.get_or_insert(1, 0).await;
+        let slow = map.get_or_insert(1, 0).await;
+
+        for value in 1..=3 {
+            map.publish(&1, value).await.unwrap();
+        }
+
+        assert_eq!(fast.next().await, 3);
+        assert_eq!((fast.lag(), slow.lag()), (0, 3));
+
+        let lags = map.subscriber_lag(&1).await.unwrap();
+        assert_eq!(lags.len(), 2);
+        assert_eq!(lags[0].subscriber(), slow.subscriber_id());
+        assert_eq!((lags[0].received(), lags[0].behind()), (0, 3));
+        assert_eq!(lags[1].behind(), 0);
+
+        drop(slow);
+        assert_eq!(map.subscriber_lag(&1).await.unwrap().len(), 1);
+        assert!(map.subscriber_lag(&2).await.is_none());
+    }
+
+    #[async_std::test]
+    async fn should_wait_until_published_value_is_observed() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, 0).await;
+        let idle = map.get_or_insert(1, 0).await;
+
+        assert_eq!(sub.next().await, 0);
+        let consumer = task::spawn(async move { (sub.next().await, sub) });
+
+        let timeout = Duration::from_millis(50);
+        assert!(!map.publish_and_wait_observed(&1, 1, timeout).await.unwrap());
+
+        let (value, mut sub) = consumer.await;
+        assert_eq!(value, 1);
+        drop(idle);
+
+        let consumer = task::spawn(async move { sub.next().await });
+        assert!(map.publish_and_wait_observed(&1, 2, timeout).await.unwrap());
+        assert_eq!(consumer.await, 2);
+    }
+
+    #[async_std::test]
+    async fn should_wait_until_value_matches() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, 2).await;
+
+        // the current value matches already, even though it was received before
+        assert_eq!(sub.next().await, 2);
+        assert_eq!(sub.wait_until(|value| value % 2 == 0).await, 2);
+
+        let _keep = sub.clone();
+        let waiter = task::spawn(async move { sub.wait_until(|value| *value > 3).await });
+
+        for value in 3..=5 {
+            map.publish(&1, value).await.unwrap();
+            task::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(waiter.await, 4);
+    }
+
+    #[async_std::test]
+    async fn should_give_up_waiting_after_timeout() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let mut sub = map.get_or_insert(1, 0).await;
+        let duration = Duration::from_millis(20);
+
+        assert_eq!(sub.next_timeout(duration).await, Ok(0));
+        assert_eq!(
+            sub.next_timeout(duration).await,
+            Err(Elapsed::new(duration))
+        );
+
+        map.publish(&1, 1).await.unwrap();
+        assert_eq!(sub.wait_until_timeout(|v| *v == 1, duration).await, Ok(1));
+
+        let e = sub.wait_until_timeout(|v| *v == 2, duration).await;
+        assert_eq!(e.unwrap_err().duration(), duration);
+    }
+
+    #[async_std::test]
+    async fn should_call_back_on_change_until_handle_dropped() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+        let _sub = map.get_or_insert(1, 0).await;
+        let wait = Duration::from_millis(20);
+
+        let seen = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let seen = seen.clone();
+            map.on_change(&1, move |value| {
+                seen.fetch_add(*value, Ordering::SeqCst);
+            })
+            .await
+            .unwrap()
+        };
+
+        for value in [1, 2] {
+            map.publish(&1, value).await.unwrap();
+            task::sleep(wait).await;
+        }
+
+        assert_eq!(seen.load(Ordering::SeqCst), 3);
+        assert!(!handle.is_finished());
+        assert_eq!(map.subscriber_count(&1).await, Some(2));
+
+        drop(handle);
+        map.publish(&1, 4).await.unwrap();
+        task::sleep(wait).await;
+
+        assert_eq!(seen.load(Ordering::SeqCst), 3);
+        assert_eq!(map.subscriber_count(&1).await, Some(1));
+        assert!(map.on_change(&2, |_| {}).await.is_none());
+    }
+
+    #[test]
+    fn should_detect_ref_count_overflow() {
+        let rc = RefCount::new(usize::MAX - 1);
+        assert!(rc.increment().is_ok());
+        assert!(rc.increment().is_err());
+        assert_eq!(rc.get(), usize::MAX);
+
+        let rc = RefCount::default();
+        assert!(rc.decrement().is_err());
+        assert!(rc.is_zero());
+    }
+
+    #[test]
+    fn should_be_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        fn assert_send<T: Send>(_: &T) {}
+
+        assert_send_sync::<SubscriptionMap<u64, String>>();
+        assert_send_sync::<SubscriptionRef<u64, String>>();
+
+        let map: SubscriptionMap<u64, String> = SubscriptionMap::new();
+        assert_send(&map.get_or_insert(1, String::new()));
+        assert_send(&map.wait_for(1));
+        assert_send(&map.publish_if_changed(&1, String::new()));
+        assert_send(&map.modify_and_publish(&1, |_| {}));
+    }
+
+    #[test]
+    fn should_move_map_across_threads() {
+        let map: SubscriptionMap<u64, String> = SubscriptionMap::new();
+
+        let handle = {
+            let map = map.clone();
+            std::thread::spawn(move || {
+                task::block_on(async move {
+                    let sub = map.get_or_insert(1, "thread".to_string()).await;
+                    task::yield_now().await;
+                    sub.latest()
+                })
+            })
+        };
+
+        assert_eq!(handle.join().unwrap(), "thread");
+        assert_eq!(task::block_on(map.entry_snapshot()).len(), 0);
+    }
+
+    #[async_std::test]
+    async fn should_resolve_wait_for_existing_key_immediately() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let _ref = map.get_or_insert(1, 1).await;
+        let waited = timeout(Duration::from_millis(100), map.wait_for(1))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(waited.latest(), 1);
+        assert_ref_count!(map, &1, 2);
+    }
+
+    #[async_std::test]
+    async fn should_resolve_wait_for_once_key_is_inserted() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let waiter = {
+            let map = map.clone();
+            task::spawn(async move { map.wait_for(1).await.unwrap().latest() })
+        };
+
+        task::sleep(Duration::from_millis(10)).await;
+        let _ref = map.get_or_insert(1, 42).await;
+
+        assert_eq!(timeout(Duration::from_secs(1), waiter).await.unwrap(), 42);
+    }
+
+    #[async_std::test]
+    async fn should_keep_waiting_if_key_is_cleaned_before_subscribing() {
+        let map: SubscriptionMap<usize, usize> = SubscriptionMap::new();
+
+        let waiter = {
+            let map = map.clone();
+            task::spawn(async move { map.wait_for(1).await.unwrap().latest() })
+        };
+
+        task::sleep(Duration::from_millis(10)).await;
+
+        let mut waiter = Box::pin(waiter);
+        let _ = map.get_or_insert(1, 1).await;
+        assert!(timeout(Duration::from_millis(50), &mut waiter)
+            .await
+            .is_err());
+
+        let _ref = map.get_or_insert(1, 2).await;
+        assert_eq!(timeout(Duration::from_secs(1), waiter).await.unwrap(), 2);
     }
 }
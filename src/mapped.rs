@@ -0,0 +1,82 @@
+use crate::SubscriptionRef;
+use futures::stream::{self, Stream};
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+
+/// A derived view on a subscription, which only yields when the mapped value changes.
+///
+/// Holds on to the underlying subscription, so the entry is kept alive while the view is in use.
+pub struct MappedSubscription<K, V, U>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+    U: Clone + Eq,
+{
+    subscription: SubscriptionRef<K, V>,
+    map: Box<dyn Fn(&V) -> U + Send + Sync>,
+    last: Option<U>,
+}
+
+impl<K, V, U> MappedSubscription<K, V, U>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+    U: Clone + Eq,
+{
+    pub(crate) fn new<F>(subscription: SubscriptionRef<K, V>, map: F) -> Self
+    where
+        F: Fn(&V) -> U + Send + Sync + 'static,
+    {
+        Self {
+            subscription,
+            map: Box::new(map),
+            last: None,
+        }
+    }
+
+    /// Wait until the mapped value differs from the previously yielded one. Like with the
+    /// underlying subscription, the first call yields the mapped current value right away.
+    pub async fn next(&mut self) -> U {
+        loop {
+            let value = self.subscription.next().await;
+            let mapped = (self.map)(&value);
+
+            if self.last.as_ref() != Some(&mapped) {
+                self.last = Some(mapped.clone());
+                return mapped;
+            }
+        }
+    }
+
+    /// The mapped current value, without waiting for a change
+    pub fn latest(&self) -> U {
+        (self.map)(&self.subscription.latest())
+    }
+
+    /// A stream of the mapped values, see [`MappedSubscription::next`]
+    pub fn into_stream(self) -> impl Stream<Item = U> {
+        stream::unfold(self, |mut mapped| async move {
+            let value = mapped.next().await;
+            Some((value, mapped))
+        })
+    }
+
+    /// Give up the mapping and get back the underlying subscription
+    pub fn into_inner(self) -> SubscriptionRef<K, V> {
+        self.subscription
+    }
+}
+
+impl<K, V, U> Debug for MappedSubscription<K, V, U>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone + Debug,
+    U: Clone + Debug + Eq,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedSubscription")
+            .field("subscription", &self.subscription)
+            .field("last", &self.last)
+            .finish()
+    }
+}
@@ -0,0 +1,106 @@
+use crate::backend::MapBackend;
+use crate::{SubscriptionMap, SubscriptionRef};
+use futures::stream::{self, BoxStream, SelectAll};
+use futures::{Stream, StreamExt};
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+
+/// Subscriptions to a fixed set of keys combined into a single stream of changes, see
+/// [`SubscriptionMap::subscribe_many`].
+///
+/// Holds one subscription ref per key, so all entries are kept alive while it is in use.
+pub struct MultiSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    keys: Vec<K>,
+    changes: SelectAll<BoxStream<'static, (K, V)>>,
+}
+
+impl<K, V> MultiSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn new(subscriptions: Vec<SubscriptionRef<K, V>>) -> Self {
+        let keys = subscriptions.iter().map(|sub| sub.key()).collect();
+        let changes = subscriptions.into_iter().map(changes).collect();
+
+        Self { keys, changes }
+    }
+
+    /// The subscribed keys
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    /// Wait until any of the entries changes. Like with a single subscription, the current value
+    /// of every entry is yielded right away first. Returns `None` if no key was subscribed to.
+    pub async fn next(&mut self) -> Option<(K, V)> {
+        self.changes.next().await
+    }
+
+    /// A stream of the changes of all entries, see [`MultiSubscription::next`]
+    pub fn into_stream(self) -> impl Stream<Item = (K, V)> {
+        self.changes
+    }
+}
+
+/// The values of a single subscription together with its key, which owns the subscription
+pub(crate) fn changes<K, V>(subscription: SubscriptionRef<K, V>) -> BoxStream<'static, (K, V)>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    stream::unfold(subscription, |mut subscription| async move {
+        let value = subscription.next().await;
+        Some(((subscription.key(), value), subscription))
+    })
+    .boxed()
+}
+
+impl<K, V> Debug for MultiSubscription<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiSubscription")
+            .field("keys", &self.keys)
+            .finish()
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Subscribe to several keys at once and observe them as a single stream of changes.
+    ///
+    /// All subscriptions are created under a single lock of the map. Keys which aren't present
+    /// are skipped, like with [`SubscriptionMap::get`], as are duplicate keys.
+    pub async fn subscribe_many<I>(&self, keys: I) -> MultiSubscription<K, V>
+    where
+        I: IntoIterator<Item = K>,
+    {
+        let mut keys: Vec<K> = keys.into_iter().collect();
+        keys.sort();
+        keys.dedup();
+
+        let shards = self.0.read_all().await;
+
+        let subscriptions = keys
+            .into_iter()
+            .filter_map(|key| {
+                let entry = shards[self.0.index(&key)].entries.get(&key)?;
+                Some(SubscriptionRef::new(self.clone(), entry).unwrap())
+            })
+            .collect();
+
+        drop(shards);
+
+        MultiSubscription::new(subscriptions)
+    }
+}
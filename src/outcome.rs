@@ -0,0 +1,21 @@
+use crate::PublishError;
+
+/// The result of [`SubscriptionMap::publish_if_present`](crate::SubscriptionMap::publish_if_present)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishOutcome {
+    /// The value differed from the current one and was published
+    Published,
+    /// The value equals the current one, nothing was published
+    Unchanged,
+    /// The key isn't present in the map, so there is nobody to publish to
+    Absent,
+    /// The entry refused the value, e.g. because it exceeded its rate limit
+    Refused(PublishError),
+}
+
+impl PublishOutcome {
+    /// Whether the value was handed to subscribers
+    pub fn is_published(&self) -> bool {
+        matches!(self, PublishOutcome::Published)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::backend::MapBackend;
+use crate::shards::Slot;
+use crate::SubscriptionMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Keeps an entry in the map without subscribing to it, see [`SubscriptionMap::pin`].
+///
+/// The entry is cleaned up once the guard is dropped, unless it still has subscribers or other
+/// pins. Like dropping a subscription ref, dropping a guard locks the map synchronously.
+#[derive(Debug)]
+#[must_use = "the entry is only kept alive while the guard is held"]
+pub struct PinGuard<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    slot: Arc<Slot<K>>,
+    id: u64,
+    map: SubscriptionMap<K, V>,
+}
+
+impl<K, V> PinGuard<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub(crate) fn new(slot: Arc<Slot<K>>, id: u64, map: SubscriptionMap<K, V>) -> Self {
+        Self { slot, id, map }
+    }
+
+    /// The key of the pinned entry, which changes if the entry is renamed, see
+    /// [`SubscriptionMap::rename`]
+    pub fn key(&self) -> K {
+        self.slot.get()
+    }
+}
+
+impl<K, V> Drop for PinGuard<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn drop(&mut self) {
+        let (key, mut state) = self.map.0.write_slot_blocking(&self.slot);
+
+        // the entry might have been evicted, or even replaced by a new one of the same key
+        let entry = match state.entries.get_mut(&key) {
+            Some(entry) if entry.id == self.id => entry,
+            _ => return,
+        };
+
+        entry.pins -= 1;
+
+        if entry.is_unused() {
+            if let Err(e) = state.remove_unused(&key) {
+                log::error!("error occurred while unpinning key {:?}: {}", key, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::shards::Shards;
+use crate::{runtime, MapEvent, SubscriptionMap};
+use futures::future::{self, AbortHandle};
+use futures::{Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Create an empty map with ordered keys which feeds its entries on demand.
+    ///
+    /// As soon as an entry gains its first subscriber, the producer is called with its key and
+    /// every value of the returned stream is published to the entry. The stream is dropped once
+    /// the entry is removed from the map, a new one is created if the key is subscribed to again
+    /// later on. Entries are still inserted with an initial value, e.g. via
+    /// [`SubscriptionMap::get_or_insert`], which subscribers see until the producer yields.
+    ///
+    /// Producers run as background tasks, which stop at the latest when the map is dropped.
+    pub fn with_producer<F, S>(producer: F) -> Self
+    where
+        F: Fn(K) -> S + Send + Sync + 'static,
+        S: Stream<Item = V> + Send + 'static,
+    {
+        let map = Self::new();
+        let events = map.events();
+
+        runtime::spawn(supervise(Arc::downgrade(&map.0), events, producer));
+        map
+    }
+}
+
+/// Start and stop producers according to the lifecycle events of the map
+async fn supervise<K, V, E, F, S>(map: Weak<Shards<K, V>>, mut events: E, producer: F)
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    E: Stream<Item = MapEvent<K>> + Unpin,
+    F: Fn(K) -> S,
+    S: Stream<Item = V> + Send + 'static,
+{
+    let mut running: BTreeMap<K, AbortHandle> = BTreeMap::new();
+
+    while let Some(event) = events.next().await {
+        match event {
+            MapEvent::FirstSubscriber(key) => {
+                // the entry might have lost and regained all subscribers without being removed
+                if running.contains_key(&key) {
+                    continue;
+                }
+
+                let values = producer(key.clone());
+                let (task, handle) = future::abortable(produce(map.clone(), key.clone(), values));
+
+                runtime::spawn(async move {
+                    let _ = task.await;
+                });
+
+                running.insert(key, handle);
+            }
+            MapEvent::Removed(key) => {
+                if let Some(handle) = running.remove(&key) {
+                    log::trace!("stopping producer of removed key {:?}", key);
+                    handle.abort();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // the map was dropped
+    for handle in running.into_values() {
+        handle.abort();
+    }
+}
+
+/// Publish every produced value to the entry until the stream ends or the entry is gone
+async fn produce<K, V, S>(map: Weak<Shards<K, V>>, key: K, values: S)
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+    S: Stream<Item = V>,
+{
+    futures::pin_mut!(values);
+
+    while let Some(value) = values.next().await {
+        let map = match map.upgrade() {
+            Some(map) => SubscriptionMap(map),
+            None => break,
+        };
+
+        if map.publish(&key, value).await.is_err() {
+            break;
+        }
+    }
+
+    log::trace!("producer of key {:?} stopped", key);
+}
@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+/// A token bucket limiting the publishes to a single entry, see
+/// [`SubscriptionMapBuilder::rate_limit`](crate::SubscriptionMapBuilder::rate_limit)
+///
+/// The bucket starts full with `burst` tokens, every publish takes one and a token is refilled
+/// every `refill`, up to the burst again.
+#[derive(Clone, Debug)]
+pub(crate) struct RateLimiter {
+    burst: u32,
+    refill: Duration,
+    tokens: u32,
+    /// When the last token was refilled, or the bucket was full last
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, refill: Duration) -> Self {
+        Self {
+            burst,
+            refill,
+            tokens: burst,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take a token if one is left, otherwise return the time until the next one is refilled
+    pub fn acquire(&mut self) -> Result<(), Duration> {
+        self.check()?;
+
+        // a full bucket doesn't accumulate refill time
+        if self.tokens == self.burst {
+            self.refilled_at = Instant::now();
+        }
+
+        self.tokens -= 1;
+        Ok(())
+    }
+
+    /// Check whether a token is left without taking it, like [`RateLimiter::acquire`]
+    pub fn check(&mut self) -> Result<(), Duration> {
+        self.refill();
+
+        if self.tokens == 0 {
+            return Err(self.refill.saturating_sub(self.refilled_at.elapsed()));
+        }
+
+        Ok(())
+    }
+
+    fn refill(&mut self) {
+        if self.refill.is_zero() {
+            self.tokens = self.burst;
+            return;
+        }
+
+        let elapsed = self.refilled_at.elapsed();
+        let refills = (elapsed.as_nanos() / self.refill.as_nanos()).min(u32::MAX as u128) as u32;
+
+        if refills == 0 {
+            return;
+        }
+
+        self.tokens = self.tokens.saturating_add(refills).min(self.burst);
+        self.refilled_at += self.refill * refills;
+    }
+}
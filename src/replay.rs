@@ -0,0 +1,15 @@
+/// What a new subscription yields first, see
+/// [`SubscriptionMap::subscribe_with`](crate::SubscriptionMap::subscribe_with)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Replay {
+    /// Yield the current value right away, like every subscription by default
+    #[default]
+    Current,
+    /// Skip the current value and only yield values published afterwards
+    Skip,
+    /// Yield up to the given number of values retained by the entry first, oldest first and
+    /// ending with the current value. Entries which don't retain values, see
+    /// [`SubscriptionMapBuilder::history`](crate::SubscriptionMapBuilder::history), yield just
+    /// the current value.
+    Last(usize),
+}
@@ -0,0 +1,52 @@
+//! The async runtime used for background tasks and timers, selected via the `runtime-async-std`
+//! and `runtime-tokio` features.
+
+use std::future::Future;
+use std::time::Duration;
+
+#[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+compile_error!("either the `runtime-async-std` or the `runtime-tokio` feature has to be enabled");
+
+#[cfg(feature = "runtime-async-std")]
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    async_std::task::spawn(future);
+}
+
+#[cfg(feature = "runtime-async-std")]
+pub(crate) async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+/// Resolve to the output of the future, or to `None` if it didn't complete within the duration
+#[cfg(feature = "runtime-async-std")]
+pub(crate) async fn timeout<F>(duration: Duration, future: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    async_std::future::timeout(duration, future).await.ok()
+}
+
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-async-std")))]
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(future);
+}
+
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-async-std")))]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Resolve to the output of the future, or to `None` if it didn't complete within the duration
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-async-std")))]
+pub(crate) async fn timeout<F>(duration: Duration, future: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    tokio::time::timeout(duration, future).await.ok()
+}
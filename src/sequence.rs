@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The version of an entry, shared by the entry state and all subscription refs
+///
+/// The version is locked while a value is handed to the observable, so subscribers reading it
+/// under the same lock see the version matching the latest value.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Sequence(Arc<Mutex<u64>>);
+
+impl Sequence {
+    pub fn get(&self) -> u64 {
+        *self.lock()
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, u64> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A value received together with its sequence number, see
+/// [`SubscriptionRef::next_sequenced`](crate::SubscriptionRef::next_sequenced)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sequenced<V> {
+    sequence: u64,
+    value: V,
+    missed: u64,
+}
+
+impl<V> Sequenced<V> {
+    pub(crate) fn new(sequence: u64, value: V, missed: u64) -> Self {
+        Self {
+            sequence,
+            value,
+            missed,
+        }
+    }
+
+    /// The version of the entry the value was published with, see
+    /// [`SubscriptionMap::current_version`](crate::SubscriptionMap::current_version)
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    /// The number of values published since the previously received one which the subscriber
+    /// never saw, because it was too slow to keep up
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// Whether values were missed, e.g. to trigger a full resync
+    pub fn has_gap(&self) -> bool {
+        self.missed > 0
+    }
+}
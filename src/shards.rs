@@ -0,0 +1,177 @@
+use crate::events::Events;
+use crate::hasher::KeyHasher;
+use crate::MapState;
+use async_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::borrow::Borrow;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Mutex;
+
+/// The state of a map split into independently locked shards, the shard of a key is selected by
+/// its hash. Maps consist of a single shard unless created via `SubscriptionMap::sharded`.
+///
+/// Whenever multiple shards have to be locked at once, they must be locked in ascending order to
+/// prevent deadlocks, see [`Shards::write_all`].
+#[derive(Debug)]
+pub(crate) struct Shards<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    shards: Box<[RwLock<MapState<K, V>>]>,
+    /// Shared with every shard, so events can be emitted without locking one
+    events: Events<K>,
+    /// Selects the shard of a key, the one of hashed storage if configured
+    hasher: KeyHasher,
+}
+
+impl<K, V> Shards<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub fn new(shards: Vec<MapState<K, V>>, hasher: KeyHasher) -> Self {
+        assert!(!shards.is_empty(), "a map needs at least one shard");
+
+        let events = Events::default();
+
+        Self {
+            shards: shards
+                .into_iter()
+                .map(|mut state| {
+                    state.events = events.clone();
+                    RwLock::new(state)
+                })
+                .collect(),
+            events,
+            hasher,
+        }
+    }
+
+    pub fn events(&self) -> &Events<K> {
+        &self.events
+    }
+
+    pub fn single(state: MapState<K, V>) -> Self {
+        Self::new(vec![state], KeyHasher::default())
+    }
+
+    /// The index of the shard responsible for the key
+    pub fn index<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash,
+    {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+
+        // hashed storage picks buckets by the low bits and tags them by the top ones, so those
+        // would be skewed within a shard
+        let hash = self.hasher.hash_one(key) >> 32;
+
+        (hash % self.shards.len() as u64) as usize
+    }
+
+    /// The shard responsible for the key
+    pub fn for_key<Q>(&self, key: &Q) -> &RwLock<MapState<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash,
+    {
+        &self.shards[self.index(key)]
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RwLock<MapState<K, V>>> {
+        self.shards.iter()
+    }
+
+    /// Lock all shards for reading in ascending order, the guards are indexed like the shards
+    pub async fn read_all(&self) -> Vec<RwLockReadGuard<'_, MapState<K, V>>> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+
+        for shard in self.shards.iter() {
+            guards.push(shard.read().await);
+        }
+
+        guards
+    }
+
+    /// Lock the shard currently holding the entry of the slot for reading, along with its key
+    pub async fn read_slot(&self, slot: &Slot<K>) -> (K, RwLockReadGuard<'_, MapState<K, V>>) {
+        loop {
+            let key = slot.get();
+            let guard = self.for_key(&key).read().await;
+
+            // the entry was renamed while waiting for the lock, so it might live in another shard
+            if slot.get() == key {
+                return (key, guard);
+            }
+        }
+    }
+
+    /// Lock the shard currently holding the entry of the slot for writing, along with its key
+    pub async fn write_slot(&self, slot: &Slot<K>) -> (K, RwLockWriteGuard<'_, MapState<K, V>>) {
+        loop {
+            let key = slot.get();
+            let guard = self.for_key(&key).write().await;
+
+            if slot.get() == key {
+                return (key, guard);
+            }
+        }
+    }
+
+    /// Like [`Shards::write_slot`] but blocks the current thread
+    pub fn write_slot_blocking(&self, slot: &Slot<K>) -> (K, RwLockWriteGuard<'_, MapState<K, V>>) {
+        loop {
+            let key = slot.get();
+            let guard = self.for_key(&key).write_blocking();
+
+            if slot.get() == key {
+                return (key, guard);
+            }
+        }
+    }
+
+    /// Lock all shards for writing in ascending order, the guards are indexed like the shards
+    pub async fn write_all(&self) -> Vec<RwLockWriteGuard<'_, MapState<K, V>>> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+
+        for shard in self.shards.iter() {
+            guards.push(shard.write().await);
+        }
+
+        guards
+    }
+}
+
+/// The key an entry is currently stored under, shared by the entry and all handles to it, so they
+/// keep finding the entry after it was renamed, see `SubscriptionMap::rename`.
+///
+/// The key is only changed while the shards of both the old and the new key are locked for
+/// writing. Handles therefore check the key again once they locked its shard, see
+/// [`Shards::write_slot`].
+#[derive(Debug)]
+pub(crate) struct Slot<K>(Mutex<K>);
+
+impl<K> Slot<K>
+where
+    K: Clone,
+{
+    pub fn new(key: K) -> Self {
+        Self(Mutex::new(key))
+    }
+
+    pub fn get(&self) -> K {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set(&self, key: K) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = key;
+    }
+}
@@ -0,0 +1,56 @@
+use crate::SubscriptionMap;
+use std::borrow::Borrow;
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// A map sharing its values behind an `Arc`, so publishing and reading values copies a pointer
+/// instead of cloning the value for every subscriber. Suited for large values like order books.
+///
+/// Change detection compares the shared values, configure
+/// [`SubscriptionMapBuilder::change_detect`](crate::SubscriptionMapBuilder::change_detect) with
+/// `|a, b| !Arc::ptr_eq(a, b)` to skip comparing large values and treat every new `Arc` as a
+/// change instead.
+///
+/// Values may be unsized, so a single map can hold heterogeneous implementations of a trait as
+/// `SharedSubscriptionMap<K, dyn Trait>`. Trait objects usually can't be compared, publish them via
+/// [`SubscriptionMap::publish`] or [`SubscriptionMap::publish_if_changed_with`].
+pub type SharedSubscriptionMap<K, V> = SubscriptionMap<K, Arc<V>>;
+
+impl<K, V> SubscriptionMap<K, Arc<V>>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: ?Sized,
+{
+    /// Publish the value shared with all subscribers, see [`SubscriptionMap::publish`]
+    pub async fn publish_shared<Q>(&self, key: &Q, value: V) -> anyhow::Result<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        V: Sized,
+    {
+        self.publish(key, Arc::new(value)).await
+    }
+
+    /// Modify the shared value in place and publish it, like
+    /// [`SubscriptionMap::try_modify_and_publish`] with a closure which can't fail.
+    ///
+    /// The value is cloned at most once, if subscribers still hold the previous `Arc`, rather
+    /// than once per subscriber.
+    pub async fn modify_shared_and_publish<Q, F, R>(&self, key: &Q, modify: F) -> anyhow::Result<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Debug + Hash + Ord,
+        V: Clone,
+        F: FnOnce(&mut V) -> R,
+    {
+        let result = self
+            .try_modify_and_publish(key, |value| {
+                Ok::<_, Infallible>(modify(Arc::make_mut(value)))
+            })
+            .await?;
+
+        Ok(result.unwrap_or_else(|never| match never {}))
+    }
+}
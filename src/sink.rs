@@ -0,0 +1,110 @@
+use crate::SubscriptionMap;
+use futures::future::BoxFuture;
+use futures::{FutureExt, Sink};
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Publishes every value sent into it to a single entry, see [`SubscriptionMap::sink_for`].
+///
+/// Values are published one after another, a value is only accepted once the previous one was
+/// published. Sending fails if the entry isn't present in the map.
+pub struct PublishSink<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    map: SubscriptionMap<K, V>,
+    key: K,
+    pending: Option<BoxFuture<'static, anyhow::Result<()>>>,
+}
+
+impl<K, V> PublishSink<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Drive the publish of the previously sent value to completion
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<anyhow::Result<()>> {
+        let pending = match &mut self.pending {
+            Some(pending) => pending,
+            None => return Poll::Ready(Ok(())),
+        };
+
+        let result = futures::ready!(pending.poll_unpin(cx));
+        self.pending = None;
+
+        Poll::Ready(result)
+    }
+}
+
+// nothing is pinned structurally, the pending publish is boxed
+impl<K, V> Unpin for PublishSink<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+}
+
+impl<K, V> Sink<V> for PublishSink<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    type Error = anyhow::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, value: V) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let map = this.map.clone();
+        let key = this.key.clone();
+
+        this.pending = Some(async move { map.publish(&key, value).await.map(drop) }.boxed());
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl<K, V> Debug for PublishSink<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PublishSink")
+            .field("key", &self.key)
+            .field("pending", &self.pending.is_some())
+            .finish()
+    }
+}
+
+impl<K, V> SubscriptionMap<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// A sink publishing every value sent into it to the entry, e.g. to forward a channel or
+    /// network stream into the map via `StreamExt::forward`.
+    ///
+    /// Values are published unconditionally, subject to debouncing if configured for the entry.
+    /// The sink doesn't subscribe, so it doesn't keep the entry alive.
+    pub fn sink_for(&self, key: K) -> PublishSink<K, V> {
+        PublishSink {
+            map: self.clone(),
+            key,
+            pending: None,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{EntryState, PublishError};
+use async_lock::MutexGuardArc;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// Reads and writes multiple entries atomically, see [`SubscriptionMap::transaction`].
+///
+/// All entries of the transaction are locked for its whole lifetime. Written values are buffered
+/// and only published once the transaction commits, subscribers never observe them before.
+///
+/// [`SubscriptionMap::transaction`]: crate::SubscriptionMap::transaction
+#[derive(Debug)]
+pub struct Transaction<K, V>
+where
+    K: Ord,
+    V: Clone,
+{
+    states: BTreeMap<K, MutexGuardArc<EntryState<V>>>,
+    writes: BTreeMap<K, V>,
+}
+
+impl<K, V> Transaction<K, V>
+where
+    K: Clone + Debug + Ord,
+    V: Clone,
+{
+    pub(crate) fn new(states: BTreeMap<K, MutexGuardArc<EntryState<V>>>) -> Self {
+        Self {
+            states,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// The keys taking part in the transaction, those passed to it which are present in the map
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.states.keys()
+    }
+
+    /// The value of the key as seen by the transaction, including its own writes. Returns `None`
+    /// if the key isn't part of the transaction.
+    pub fn get(&self, key: &K) -> Option<V> {
+        match self.writes.get(key) {
+            Some(value) => Some(value.clone()),
+            None => self.states.get(key).map(|state| state.latest()),
+        }
+    }
+
+    /// Buffer a value to be published on commit, replacing earlier writes of the same key. Returns
+    /// whether the key is part of the transaction, other keys can't be written.
+    pub fn set(&mut self, key: K, value: V) -> bool {
+        if !self.states.contains_key(&key) {
+            log::warn!("unable to write key {:?} outside of transaction", key);
+            return false;
+        }
+
+        self.writes.insert(key, value);
+        true
+    }
+
+    /// Publish all buffered writes if every written entry accepts them, otherwise nothing is
+    /// published. The entries are unlocked afterwards.
+    pub(crate) fn commit(mut self) -> Result<(), PublishError> {
+        for key in self.writes.keys() {
+            let state = self.states.get_mut(key).expect("written keys are locked");
+            state.check_admission()?;
+        }
+
+        for (key, value) in self.writes {
+            let state = self.states.get_mut(&key).expect("written keys are locked");
+            state.try_publish(value)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use crate::backend::MapBackend;
+use crate::shards::{Shards, Slot};
+use crate::{SubscriptionMap, SubscriptionRef};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+
+/// A handle to an entry which doesn't count as a subscription, see
+/// [`SubscriptionRef::downgrade`].
+///
+/// Neither the entry nor the map are kept alive by a weak handle. It only refers to the very
+/// entry it was created from: once that entry is cleaned up, upgrading fails even if a new entry
+/// of the same key was inserted in the meantime.
+#[derive(Clone, Debug)]
+pub struct WeakSubscriptionRef<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    slot: Arc<Slot<K>>,
+    id: u64,
+    map: Weak<Shards<K, V>>,
+}
+
+impl<K, V> WeakSubscriptionRef<K, V>
+where
+    K: Clone + Debug + Eq + Hash + Ord,
+    V: Clone,
+{
+    pub(crate) fn new(subscription: &SubscriptionRef<K, V>) -> Self {
+        Self {
+            slot: subscription.slot.clone(),
+            id: subscription.id,
+            map: Arc::downgrade(&subscription.owner.0),
+        }
+    }
+
+    /// The key of the referenced entry, which changes if the entry is renamed, see
+    /// [`SubscriptionMap::rename`]
+    pub fn key(&self) -> K {
+        self.slot.get()
+    }
+
+    /// Subscribe to the referenced entry again, or return `None` if it was removed from the map
+    /// or the map was dropped.
+    pub async fn upgrade(&self) -> Option<SubscriptionRef<K, V>> {
+        let map = SubscriptionMap(self.map.upgrade()?);
+        let (key, state) = map.0.read_slot(&self.slot).await;
+
+        let entry = match state.entries.get(&key) {
+            Some(entry) if entry.id == self.id => entry,
+            _ => return None,
+        };
+
+        Some(SubscriptionRef::new(map.clone(), entry).unwrap())
+    }
+}